@@ -0,0 +1,159 @@
+//! Canonical encoding of the state and the keccak root committing to it.
+//!
+//! Entries are sorted by address and entries holding zero (a zero nonce or a
+//! zero balance) are left out, since they are indistinguishable from missing
+//! ones. Two states with the same nonces and balances therefore always have the
+//! same encoding, no matter how they were built.
+//!
+//! `AppState` is encoded as `app || nonce_count || (user || nonce)*`, and
+//! `WalletState` as `app_count || app_section* || balance_count || (user || balance)*`,
+//! where each app section is the encoding of the `AppState` of the app (apps
+//! without a non-zero nonce are left out). Counts and nonces are big-endian
+//! `u64`s, addresses are 20 bytes and balances are big-endian 32-byte words.
+//!
+//! The two roots are distinct commitments: the root of an `AppState` commits
+//! to the nonces of one app, and that of a `WalletState` to the nonces of every
+//! app and to the balances. An app checks its own state against the wallet by
+//! finding its section in the wallet encoding.
+
+use alloy_core::primitives::{keccak256, Address, B256, U256};
+
+use crate::{AppNonces, AppState, WalletState};
+
+impl AppNonces {
+    // non-zero nonces sorted by user address
+    fn sorted_nonces(&self) -> Vec<(Address, u64)> {
+        let mut nonces: Vec<(Address, u64)> = self
+            .nonces
+            .iter()
+            .filter(|(_, nonce)| **nonce != 0)
+            .map(|(user, nonce)| (*user, *nonce))
+            .collect();
+        nonces.sort_unstable();
+        nonces
+    }
+}
+
+// the section of an app in the encoding
+fn encode_app(app: &Address, nonces: &[(Address, u64)], bytes: &mut Vec<u8>) {
+    bytes.extend_from_slice(app.as_slice());
+    bytes.extend_from_slice(&(nonces.len() as u64).to_be_bytes());
+    for (user, nonce) in nonces {
+        bytes.extend_from_slice(user.as_slice());
+        bytes.extend_from_slice(&nonce.to_be_bytes());
+    }
+}
+
+impl WalletState {
+    pub fn to_canonical_bytes(&self) -> Vec<u8> {
+        let mut apps: Vec<(&Address, Vec<(Address, u64)>)> = self
+            .app_nonces
+            .iter()
+            .map(|(app, nonces)| (app, nonces.sorted_nonces()))
+            .filter(|(_, nonces)| !nonces.is_empty())
+            .collect();
+        apps.sort_unstable_by_key(|(app, _)| *app);
+
+        let mut balances: Vec<(Address, U256)> = self
+            .balances
+            .iter()
+            .filter(|(_, balance)| !balance.is_zero())
+            .map(|(user, balance)| (*user, *balance))
+            .collect();
+        balances.sort_unstable();
+
+        let nonce_count: usize = apps.iter().map(|(_, nonces)| nonces.len()).sum();
+        let mut bytes =
+            Vec::with_capacity(16 + apps.len() * 28 + nonce_count * 28 + balances.len() * 52);
+        bytes.extend_from_slice(&(apps.len() as u64).to_be_bytes());
+        for (app, nonces) in apps {
+            encode_app(app, &nonces, &mut bytes);
+        }
+        bytes.extend_from_slice(&(balances.len() as u64).to_be_bytes());
+        for (user, balance) in balances {
+            bytes.extend_from_slice(user.as_slice());
+            bytes.extend_from_slice(&balance.to_be_bytes::<32>());
+        }
+        bytes
+    }
+
+    /// Keccak hash of the canonical encoding of all nonces and balances.
+    pub fn state_root(&self) -> B256 {
+        keccak256(self.to_canonical_bytes())
+    }
+}
+
+impl AppState {
+    pub fn to_canonical_bytes(&self) -> Vec<u8> {
+        let nonces = self.nonces.sorted_nonces();
+        let mut bytes = Vec::with_capacity(28 + nonces.len() * 28);
+        encode_app(&self.address, &nonces, &mut bytes);
+        bytes
+    }
+
+    /// Keccak hash of the canonical encoding of the app nonces, which is not
+    /// the root of any `WalletState`.
+    pub fn state_root(&self) -> B256 {
+        keccak256(self.to_canonical_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_core::primitives::address;
+
+    use super::*;
+    use crate::tests::DOMAIN;
+
+    #[test]
+    fn root_ignores_insertion_order_and_zeros() {
+        let alice = address!("0000000000000000000000000000000000000001");
+        let bob = address!("0000000000000000000000000000000000000002");
+        let app = address!("0000000000000000000000000000000000000003");
+
+        let mut first = WalletState::new(DOMAIN);
+        first.deposit(alice, U256::from(10));
+        first.deposit(bob, U256::from(20));
+        let mut nonces = AppNonces::default();
+        nonces.set_nonce(alice, 1);
+        nonces.set_nonce(bob, 0);
+        first.add_app_nonce(app, nonces);
+
+        let mut second = WalletState::new(DOMAIN);
+        second.deposit(bob, U256::from(20));
+        second.deposit(alice, U256::from(10));
+        second.withdraw_forced(app, U256::from(1));
+        let mut nonces = AppNonces::default();
+        nonces.set_nonce(alice, 1);
+        second.add_app_nonce(app, nonces);
+
+        assert_eq!(first.state_root(), second.state_root());
+
+        second.deposit(alice, U256::from(1));
+        assert_ne!(first.state_root(), second.state_root());
+    }
+
+    #[test]
+    fn app_state_encoding() {
+        let alice = address!("0000000000000000000000000000000000000001");
+        let mut app_state = AppState::new(DOMAIN, Address::ZERO);
+        app_state.nonces.set_nonce(alice, 7);
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(Address::ZERO.as_slice());
+        expected.extend_from_slice(&1u64.to_be_bytes());
+        expected.extend_from_slice(alice.as_slice());
+        expected.extend_from_slice(&7u64.to_be_bytes());
+        assert_eq!(app_state.to_canonical_bytes(), expected);
+        assert_eq!(app_state.state_root(), keccak256(&expected));
+
+        // the wallet holds the same encoding as the section of the app
+        let mut wallet_state = WalletState::new(DOMAIN);
+        wallet_state.add_app_nonce(Address::ZERO, app_state.nonces.clone());
+        let mut wallet = 1u64.to_be_bytes().to_vec();
+        wallet.extend_from_slice(&expected);
+        wallet.extend_from_slice(&0u64.to_be_bytes());
+        assert_eq!(wallet_state.to_canonical_bytes(), wallet);
+        assert_ne!(wallet_state.state_root(), app_state.state_root());
+    }
+}
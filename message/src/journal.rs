@@ -0,0 +1,92 @@
+/// Handle to a point in the state history that can be rolled back to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Checkpoint(usize);
+
+// Undo log of changes made while at least one checkpoint is open.
+// Nothing is recorded when there are no open checkpoints, so keeping
+// the journal around is free for callers that never use checkpoints.
+#[derive(Clone, Debug)]
+pub(crate) struct Journal<E> {
+    entries: Vec<E>,
+    // position of each open checkpoint in the history of changes
+    checkpoints: Vec<usize>,
+    // id of the oldest open checkpoint, and position of the oldest
+    // entry, which move forward as old checkpoints are released
+    first_checkpoint: usize,
+    first_entry: usize,
+}
+
+impl<E> Default for Journal<E> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            checkpoints: Vec::new(),
+            first_checkpoint: 0,
+            first_entry: 0,
+        }
+    }
+}
+
+impl<E> Journal<E> {
+    pub(crate) fn checkpoint(&mut self) -> Checkpoint {
+        self.checkpoints.push(self.first_entry + self.entries.len());
+        Checkpoint(self.first_checkpoint + self.checkpoints.len() - 1)
+    }
+
+    pub(crate) fn record(&mut self, entry: E) {
+        if !self.checkpoints.is_empty() {
+            self.entries.push(entry);
+        }
+    }
+
+    // closes `checkpoint` (and every checkpoint taken after it), returning the
+    // changes made since it was taken, most recent first
+    pub(crate) fn rollback(&mut self, checkpoint: Checkpoint) -> Vec<E> {
+        let Some(index) = checkpoint.0.checked_sub(self.first_checkpoint) else {
+            return Vec::new();
+        };
+        let Some(&position) = self.checkpoints.get(index) else {
+            return Vec::new();
+        };
+        self.checkpoints.truncate(index);
+        let mut undone = self.entries.split_off(position - self.first_entry);
+        undone.reverse();
+        undone
+    }
+
+    // closes `checkpoint` (and every checkpoint taken after it), keeping the changes;
+    // they can still be undone by rolling back to an earlier checkpoint
+    pub(crate) fn commit(&mut self, checkpoint: Checkpoint) {
+        let Some(index) = checkpoint.0.checked_sub(self.first_checkpoint) else {
+            return;
+        };
+        self.checkpoints.truncate(index);
+        if self.checkpoints.is_empty() {
+            self.forget_entries(self.entries.len());
+        }
+    }
+
+    // closes `checkpoint` (and every checkpoint taken before it) for good, keeping
+    // the changes; the checkpoints taken after it stay open
+    pub(crate) fn release(&mut self, checkpoint: Checkpoint) {
+        let Some(index) = checkpoint.0.checked_sub(self.first_checkpoint) else {
+            return;
+        };
+        if index >= self.checkpoints.len() {
+            return;
+        }
+        self.checkpoints.drain(..=index);
+        self.first_checkpoint += index + 1;
+        // only the changes after the oldest open checkpoint can be undone now
+        let forgotten = match self.checkpoints.first() {
+            Some(&position) => position - self.first_entry,
+            None => self.entries.len(),
+        };
+        self.forget_entries(forgotten);
+    }
+
+    fn forget_entries(&mut self, count: usize) {
+        self.entries.drain(..count);
+        self.first_entry += count;
+    }
+}
@@ -1,7 +1,8 @@
 use std::collections::HashMap;
+use std::fmt;
 
 use alloy_core::{
-    primitives::{Address, SignatureError, U256},
+    primitives::{keccak256, Address, SignatureError, B256, U256},
     sol,
     sol_types::{Eip712Domain, SolStruct},
 };
@@ -9,7 +10,16 @@ use alloy_signer::Signature;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug)]
+mod commitment;
+mod journal;
+mod preconf;
+mod typed_data;
+pub use journal::Checkpoint;
+use journal::Journal;
+pub use preconf::{Preconfirmation, SignedPreconfirmation};
+pub use typed_data::{TypedData, TypedField};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct WalletState {
     pub domain: Eip712Domain,
 
@@ -18,8 +28,30 @@ pub struct WalletState {
 
     // user address to balance
     pub balances: HashMap<Address, U256>,
+
+    // changes made since the oldest open checkpoint
+    #[serde(skip)]
+    journal: Journal<WalletChange>,
+}
+
+// previous values of the state, used to undo a change
+#[derive(Clone, Debug)]
+enum WalletChange {
+    App(Address),
+    Nonce(Address, Address, Option<u64>),
+    Balance(Address, Option<U256>),
+}
+
+impl PartialEq for WalletState {
+    fn eq(&self, other: &Self) -> bool {
+        self.domain == other.domain
+            && self.app_nonces == other.app_nonces
+            && self.balances == other.balances
+    }
 }
 
+impl Eq for WalletState {}
+
 impl WalletState {
     pub fn verify_batch(&mut self, batch: Batch) -> Vec<Transaction> {
         batch
@@ -28,26 +60,46 @@ impl WalletState {
             .filter_map(|tx| self.verify_single(batch.sequencer_payment_address, tx))
             .collect()
     }
-    // TODO: create custom error type in order to explain why it did not work
+    // a rejected transaction leaves the state untouched
     pub fn verify_single(
         &mut self,
         sequencer_payment_address: Address,
         tx: &WireTransaction,
     ) -> Option<Transaction> {
-        let app_nonce = self.app_nonces.entry(tx.app).or_default();
-        let tx_opt = app_nonce.verify_tx(tx, &self.domain);
-
-        if let Some(ref tx) = tx_opt {
-            let cost_opt = tx.cost();
-            let payment = if let Some(cost) = cost_opt {
-                self.withdraw_forced(tx.sender, cost)
-            } else {
-                self.withdraw_forced(tx.sender, U256::MAX)
-            };
-            self.deposit(sequencer_payment_address, payment);
+        self.try_single(sequencer_payment_address, tx).ok()
+    }
+
+    /// Like `verify_single`, telling why a transaction was rejected.
+    pub fn try_single(
+        &mut self,
+        sequencer_payment_address: Address,
+        tx: &WireTransaction,
+    ) -> Result<Transaction, TxError> {
+        let tx = match self.app_nonces.get(&tx.app) {
+            Some(app_nonce) => app_nonce.check_tx(tx, &self.domain),
+            None => AppNonces::default().check_tx(tx, &self.domain),
+        }?;
+
+        if !self.app_nonces.contains_key(&tx.app) {
+            self.journal.record(WalletChange::App(tx.app));
         }
+        let prev = self
+            .app_nonces
+            .entry(tx.app)
+            .or_default()
+            .bump_nonce(tx.sender);
+        self.journal
+            .record(WalletChange::Nonce(tx.app, tx.sender, prev));
+
+        let cost_opt = tx.cost();
+        let payment = if let Some(cost) = cost_opt {
+            self.withdraw_forced(tx.sender, cost)
+        } else {
+            self.withdraw_forced(tx.sender, U256::MAX)
+        };
+        self.deposit(sequencer_payment_address, payment);
 
-        tx_opt
+        Ok(tx)
     }
 
     pub fn verify_raw_batch(&mut self, raw_batch: &[u8]) -> postcard::Result<Vec<Transaction>> {
@@ -56,11 +108,13 @@ impl WalletState {
     }
 
     pub fn deposit(&mut self, user: Address, value: U256) {
+        self.record_balance(user);
         let balance = self.balances.entry(user).or_default();
         *balance += value;
     }
 
     pub fn withdraw_forced(&mut self, user: Address, value: U256) -> U256 {
+        self.record_balance(user);
         let balance = self.balances.entry(user).or_default();
         if *balance < value {
             let prev = *balance;
@@ -71,6 +125,53 @@ impl WalletState {
             value
         }
     }
+
+    fn record_balance(&mut self, user: Address) {
+        let prev = self.balances.get(&user).copied();
+        self.journal.record(WalletChange::Balance(user, prev));
+    }
+
+    /// Opens a checkpoint; every change made from now on can be undone with
+    /// `rollback` until the checkpoint is closed with `commit`.
+    /// Checkpoints can be nested.
+    pub fn checkpoint(&mut self) -> Checkpoint {
+        self.journal.checkpoint()
+    }
+
+    /// Undoes every change made since `checkpoint` was taken and closes it,
+    /// together with any checkpoint taken after it.
+    pub fn rollback(&mut self, checkpoint: Checkpoint) {
+        for change in self.journal.rollback(checkpoint) {
+            match change {
+                WalletChange::App(app) => {
+                    self.app_nonces.remove(&app);
+                }
+                WalletChange::Nonce(app, user, prev) => {
+                    if let Some(app_nonces) = self.app_nonces.get_mut(&app) {
+                        app_nonces.restore_nonce(user, prev);
+                    }
+                }
+                WalletChange::Balance(user, Some(prev)) => {
+                    self.balances.insert(user, prev);
+                }
+                WalletChange::Balance(user, None) => {
+                    self.balances.remove(&user);
+                }
+            }
+        }
+    }
+
+    /// Keeps the changes made since `checkpoint` and closes it, together with
+    /// any checkpoint taken after it.
+    pub fn commit(&mut self, checkpoint: Checkpoint) {
+        self.journal.commit(checkpoint)
+    }
+
+    /// Keeps the changes made before `checkpoint` for good, closing it together
+    /// with any checkpoint taken before it. Later checkpoints stay open.
+    pub fn release(&mut self, checkpoint: Checkpoint) {
+        self.journal.release(checkpoint)
+    }
 }
 
 impl WalletState {
@@ -79,6 +180,7 @@ impl WalletState {
             domain,
             app_nonces: HashMap::new(),
             balances: HashMap::new(),
+            journal: Journal::default(),
         }
     }
     pub fn add_app_nonce(&mut self, address: Address, nonces: AppNonces) {
@@ -86,7 +188,7 @@ impl WalletState {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AppState {
     pub domain: Eip712Domain,
     pub address: Address,
@@ -122,15 +224,28 @@ impl AppState {
     }
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct AppNonces {
     // user address to nonce
     pub nonces: HashMap<Address, u64>,
+
+    // previous nonces changed since the oldest open checkpoint
+    #[serde(skip)]
+    journal: Journal<(Address, Option<u64>)>,
+}
+
+impl PartialEq for AppNonces {
+    fn eq(&self, other: &Self) -> bool {
+        self.nonces == other.nonces
+    }
 }
 
+impl Eq for AppNonces {}
+
 impl AppNonces {
     pub fn set_nonce(&mut self, address: Address, value: u64) {
-        self.nonces.insert(address, value);
+        let prev = self.nonces.insert(address, value);
+        self.journal.record((address, prev));
     }
     pub fn get_nonce(&self, address: &Address) -> Option<&u64> {
         self.nonces.get(address)
@@ -140,21 +255,91 @@ impl AppNonces {
         tx: &WireTransaction,
         domain: &Eip712Domain,
     ) -> Option<Transaction> {
-        let Some(tx) = tx.verify(&domain) else {
-            return None;
+        let tx = self.check_tx(tx, domain).ok()?;
+        let prev = self.bump_nonce(tx.sender);
+        self.journal.record((tx.sender, prev));
+        Some(tx)
+    }
+
+    // verifies signature and nonce without changing anything
+    fn check_tx(
+        &self,
+        tx: &WireTransaction,
+        domain: &Eip712Domain,
+    ) -> Result<Transaction, TxError> {
+        let tx = tx.verify(domain).ok_or(TxError::InvalidSignature)?;
+
+        let expected_nonce = self.nonces.get(&tx.sender).copied().unwrap_or(0);
+
+        if expected_nonce != tx.nonce {
+            return Err(TxError::WrongNonce {
+                expected: expected_nonce,
+                got: tx.nonce,
+            });
+        }
+
+        Ok(tx)
+    }
+
+    // returns the nonce before the increment, if the user had one
+    fn bump_nonce(&mut self, address: Address) -> Option<u64> {
+        let prev = self.nonces.get(&address).copied();
+        self.nonces.insert(address, prev.unwrap_or(0) + 1);
+        prev
+    }
+
+    fn restore_nonce(&mut self, address: Address, prev: Option<u64>) {
+        match prev {
+            Some(nonce) => self.nonces.insert(address, nonce),
+            None => self.nonces.remove(&address),
         };
+    }
 
-        let expected_nonce = self.nonces.entry(tx.sender).or_insert(0);
+    /// See `WalletState::checkpoint`.
+    pub fn checkpoint(&mut self) -> Checkpoint {
+        self.journal.checkpoint()
+    }
 
-        if *expected_nonce != tx.nonce {
-            return None;
+    /// See `WalletState::rollback`.
+    pub fn rollback(&mut self, checkpoint: Checkpoint) {
+        for (address, prev) in self.journal.rollback(checkpoint) {
+            self.restore_nonce(address, prev);
         }
+    }
 
-        *expected_nonce += 1;
-        Some(tx)
+    /// See `WalletState::commit`.
+    pub fn commit(&mut self, checkpoint: Checkpoint) {
+        self.journal.commit(checkpoint)
+    }
+
+    /// See `WalletState::release`.
+    pub fn release(&mut self, checkpoint: Checkpoint) {
+        self.journal.release(checkpoint)
+    }
+}
+
+/// Why a transaction was rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxError {
+    /// No sender can be recovered from the signature.
+    InvalidSignature,
+    /// The nonce is not the next one of the sender for the app.
+    WrongNonce { expected: u64, got: u64 },
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxError::InvalidSignature => write!(f, "invalid signature"),
+            TxError::WrongNonce { expected, got } => {
+                write!(f, "wrong nonce, expected {}, got {}", expected, got)
+            }
+        }
     }
 }
 
+impl std::error::Error for TxError {}
+
 #[derive(Clone, Debug)]
 pub struct Transaction {
     pub sender: Address,
@@ -226,6 +411,17 @@ impl WireTransaction {
             data: self.data.clone(),
         })
     }
+
+    // number of bytes the transaction takes in an encoded batch
+    pub fn encoded_len(&self) -> usize {
+        postcard::to_stdvec(self).unwrap().len()
+    }
+
+    /// Identifies the transaction: the keccak hash of its encoding in a batch,
+    /// signature included, so anyone reading the batch can compute it.
+    pub fn hash(&self) -> B256 {
+        keccak256(postcard::to_stdvec(self).unwrap())
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
@@ -312,6 +508,18 @@ mod tests {
        verifying_contract: Address::ZERO,
     );
 
+    // a transaction of `signer` to the zero app
+    pub fn wire_tx(signer: &LocalWallet, nonce: u64) -> WireTransaction {
+        let message = SigningMessage {
+            app: Address::ZERO,
+            nonce,
+            max_gas_price: 1,
+            data: vec![1, 2, 3].into(),
+        };
+        let signature = signer.sign_typed_data_sync(&message, &DOMAIN).unwrap();
+        WireTransaction::from_signed_transaction(&SignedTransaction { message, signature })
+    }
+
     fn produce_tx() -> (String, Address) {
         let json = r#"
         {
@@ -389,4 +597,167 @@ mod tests {
             println!("{:?}", tx);
         }
     }
+
+    #[test]
+    fn rejected_tx_keeps_state() {
+        let (tx_json, _) = produce_tx();
+        let tx: SignedTransaction = serde_json::from_str(&tx_json).unwrap();
+        let mut wire_tx = WireTransaction::from_signed_transaction(&tx);
+        wire_tx.nonce = 1;
+
+        let mut wallet_state = WalletState::new(DOMAIN);
+        let before = wallet_state.clone();
+        assert!(wallet_state
+            .verify_single(Address::ZERO, &wire_tx)
+            .is_none());
+        assert_eq!(
+            wallet_state
+                .try_single(Address::ZERO, &wire_tx)
+                .unwrap_err(),
+            TxError::WrongNonce {
+                expected: 0,
+                got: 1
+            }
+        );
+        assert_eq!(before, wallet_state);
+
+        wire_tx.nonce = 0;
+        assert!(wallet_state
+            .verify_single(Address::ZERO, &wire_tx)
+            .is_some());
+        assert_ne!(before, wallet_state);
+    }
+
+    #[test]
+    fn checkpoint_rollback() {
+        let (tx_json, signer) = produce_tx();
+        let tx: SignedTransaction = serde_json::from_str(&tx_json).unwrap();
+        let wire_tx = WireTransaction::from_signed_transaction(&tx);
+
+        let mut wallet_state = WalletState::new(DOMAIN);
+        wallet_state.deposit(signer, U256::from(1000));
+        let initial = wallet_state.clone();
+
+        let outer = wallet_state.checkpoint();
+        wallet_state.deposit(signer, U256::from(5));
+        let after_deposit = wallet_state.clone();
+
+        let inner = wallet_state.checkpoint();
+        assert!(wallet_state
+            .verify_single(Address::ZERO, &wire_tx)
+            .is_some());
+        wallet_state.rollback(inner);
+        assert_eq!(wallet_state, after_deposit);
+
+        // committing an inner checkpoint keeps its changes undoable by the outer one
+        let inner = wallet_state.checkpoint();
+        assert!(wallet_state
+            .verify_single(Address::ZERO, &wire_tx)
+            .is_some());
+        wallet_state.commit(inner);
+        assert_eq!(
+            wallet_state.app_nonces[&Address::ZERO].get_nonce(&signer),
+            Some(&1)
+        );
+        wallet_state.rollback(outer);
+        assert_eq!(wallet_state, initial);
+
+        // without open checkpoints nothing is recorded
+        wallet_state.deposit(signer, U256::from(5));
+        let checkpoint = wallet_state.checkpoint();
+        wallet_state.rollback(checkpoint);
+        assert_eq!(wallet_state.balances[&signer], U256::from(1005));
+    }
+
+    #[test]
+    fn release_keeps_later_checkpoints() {
+        let signer = Address::repeat_byte(1);
+        let mut wallet_state = WalletState::new(DOMAIN);
+
+        // a batch in flight, and the open batch after it
+        let in_flight = wallet_state.checkpoint();
+        wallet_state.deposit(signer, U256::from(1));
+        let open = wallet_state.checkpoint();
+        wallet_state.deposit(signer, U256::from(2));
+        let posted = wallet_state.clone();
+
+        // the batch in flight is posted, its changes are kept for good
+        wallet_state.release(in_flight);
+        wallet_state.rollback(in_flight);
+        assert_eq!(wallet_state, posted);
+
+        let next = wallet_state.checkpoint();
+        wallet_state.deposit(signer, U256::from(4));
+        wallet_state.release(open);
+        wallet_state.rollback(next);
+        assert_eq!(wallet_state.balances[&signer], U256::from(3));
+
+        // new checkpoints do not reuse released ones
+        let checkpoint = wallet_state.checkpoint();
+        wallet_state.deposit(signer, U256::from(8));
+        wallet_state.rollback(open);
+        assert_eq!(wallet_state.balances[&signer], U256::from(11));
+        wallet_state.rollback(checkpoint);
+        assert_eq!(wallet_state.balances[&signer], U256::from(3));
+    }
+
+    #[test]
+    fn stale_commit_is_ignored() {
+        let signer = Address::repeat_byte(1);
+        let mut wallet_state = WalletState::new(DOMAIN);
+
+        let in_flight = wallet_state.checkpoint();
+        wallet_state.deposit(signer, U256::from(1));
+        let open = wallet_state.checkpoint();
+        wallet_state.deposit(signer, U256::from(2));
+
+        // committing a released checkpoint leaves the later ones open
+        wallet_state.release(in_flight);
+        wallet_state.commit(in_flight);
+        wallet_state.rollback(open);
+        assert_eq!(wallet_state.balances[&signer], U256::from(1));
+    }
+
+    #[test]
+    fn app_nonces_rollback() {
+        let (tx_json, signer) = produce_tx();
+        let tx: SignedTransaction = serde_json::from_str(&tx_json).unwrap();
+        let wire_tx = WireTransaction::from_signed_transaction(&tx);
+
+        let mut app_state = AppState::new(DOMAIN, Address::ZERO);
+        let checkpoint = app_state.nonces.checkpoint();
+        assert!(app_state.nonces.verify_tx(&wire_tx, &DOMAIN).is_some());
+        assert_eq!(app_state.nonces.get_nonce(&signer), Some(&1));
+        app_state.nonces.rollback(checkpoint);
+        assert_eq!(app_state.nonces.get_nonce(&signer), None);
+    }
+
+    #[test]
+    fn encoded_len_adds_up() {
+        let (tx_json, _) = produce_tx();
+        let tx: SignedTransaction = serde_json::from_str(&tx_json).unwrap();
+        let wire_tx = WireTransaction::from_signed_transaction(&tx);
+
+        let mut builder = BatchBuilder::new(Address::ZERO);
+        builder.add(tx.clone());
+        let one = builder.clone().build().to_bytes().len();
+        builder.add(tx);
+        let two = builder.build().to_bytes().len();
+        assert_eq!(two - one, wire_tx.encoded_len());
+    }
+
+    #[test]
+    fn hash_identifies_the_transaction() {
+        let (tx_json, _) = produce_tx();
+        let tx: SignedTransaction = serde_json::from_str(&tx_json).unwrap();
+        let wire_tx = WireTransaction::from_signed_transaction(&tx);
+        assert_eq!(
+            wire_tx.hash(),
+            WireTransaction::from_signed_transaction(&tx).hash()
+        );
+
+        let mut other = wire_tx.clone();
+        other.nonce += 1;
+        assert_ne!(other.hash(), wire_tx.hash());
+    }
 }
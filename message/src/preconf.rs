@@ -0,0 +1,107 @@
+use alloy_core::{
+    primitives::{Address, SignatureError},
+    sol,
+    sol_types::{Eip712Domain, SolStruct},
+};
+use alloy_signer::Signature;
+use serde::{Deserialize, Serialize};
+
+use crate::Batch;
+
+sol! {
+    /// A promise from the sequencer that the transaction `tx_hash` will be at
+    /// `position` in its batch number `batch`. Batches are numbered from zero,
+    /// in the order the sequencer posts them to the app.
+    #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+    struct Preconfirmation {
+        bytes32 tx_hash;
+        uint64 batch;
+        uint64 position;
+    }
+}
+
+/// A preconfirmation, signed by the sequencer with EIP-712 under the same
+/// domain as the transactions.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct SignedPreconfirmation {
+    pub preconfirmation: Preconfirmation,
+    pub signature: Signature,
+}
+
+impl SignedPreconfirmation {
+    pub fn recover(&self, domain: &Eip712Domain) -> Result<Address, SignatureError> {
+        let signing_hash = self.preconfirmation.eip712_signing_hash(domain);
+        self.signature.recover_address_from_prehash(&signing_hash)
+    }
+
+    /// Whether the promise was made by `sequencer`.
+    pub fn verify(&self, domain: &Eip712Domain, sequencer: Address) -> bool {
+        self.recover(domain).is_ok_and(|signer| signer == sequencer)
+    }
+
+    /// Whether `batch`, taken to be batch number `preconfirmation.batch` of
+    /// the sequencer, holds the promised transaction at the promised position.
+    pub fn is_kept_by(&self, batch: &Batch) -> bool {
+        usize::try_from(self.preconfirmation.position)
+            .ok()
+            .and_then(|position| batch.txs.get(position))
+            .is_some_and(|tx| tx.hash() == self.preconfirmation.tx_hash)
+    }
+
+    /// Whether this proves that `sequencer` broke its promise with `batch`,
+    /// its batch number `preconfirmation.batch`.
+    pub fn is_broken_by(&self, domain: &Eip712Domain, sequencer: Address, batch: &Batch) -> bool {
+        self.verify(domain, sequencer) && !self.is_kept_by(batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{wire_tx, DOMAIN};
+    use alloy_signer::SignerSync;
+    use alloy_signer_wallet::LocalWallet;
+
+    #[test]
+    fn broken_promise_is_provable() {
+        let sequencer = LocalWallet::random();
+        let user = LocalWallet::random();
+        let (first, second) = (wire_tx(&user, 0), wire_tx(&user, 1));
+
+        let preconfirmation = Preconfirmation {
+            tx_hash: second.hash(),
+            batch: 4,
+            position: 1,
+        };
+        let signature = sequencer
+            .sign_typed_data_sync(&preconfirmation, &DOMAIN)
+            .unwrap();
+        let signed = SignedPreconfirmation {
+            preconfirmation,
+            signature,
+        };
+        assert!(signed.verify(&DOMAIN, sequencer.address()));
+        assert!(!signed.verify(&DOMAIN, user.address()));
+
+        let kept = Batch {
+            sequencer_payment_address: sequencer.address(),
+            txs: vec![first.clone(), second.clone()],
+        };
+        assert!(signed.is_kept_by(&kept));
+        assert!(!signed.is_broken_by(&DOMAIN, sequencer.address(), &kept));
+
+        let reordered = Batch {
+            sequencer_payment_address: sequencer.address(),
+            txs: vec![second, first],
+        };
+        assert!(signed.is_broken_by(&DOMAIN, sequencer.address(), &reordered));
+        // only a promise signed by the sequencer proves anything
+        assert!(!signed.is_broken_by(&DOMAIN, user.address(), &reordered));
+
+        let empty = Batch {
+            sequencer_payment_address: sequencer.address(),
+            txs: vec![],
+        };
+        assert!(signed.is_broken_by(&DOMAIN, sequencer.address(), &empty));
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+
+use alloy_core::{
+    primitives::B256,
+    sol_types::{Eip712Domain, SolStruct},
+};
+use serde::{Deserialize, Serialize};
+
+/// A member of an EIP-712 struct, as listed in `TypedData::types`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct TypedField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+}
+
+/// What `eth_signTypedData_v4` takes to sign `message` under `domain`, as
+/// wallets like MetaMask expect it.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TypedData<T> {
+    pub types: BTreeMap<String, Vec<TypedField>>,
+    pub primary_type: String,
+    pub domain: Eip712Domain,
+    pub message: T,
+}
+
+impl<T: SolStruct> TypedData<T> {
+    /// Only structs without other structs as members are supported, which is
+    /// the case of `SigningMessage`.
+    pub fn new(domain: &Eip712Domain, message: T) -> Self {
+        let mut types = BTreeMap::new();
+        types.insert("EIP712Domain".to_string(), domain_fields(domain));
+        types.insert(T::NAME.to_string(), fields(&T::eip712_root_type()));
+        Self {
+            types,
+            primary_type: T::NAME.to_string(),
+            domain: domain.clone(),
+            message,
+        }
+    }
+
+    /// The hash the wallet signs.
+    pub fn signing_hash(&self) -> B256 {
+        self.message.eip712_signing_hash(&self.domain)
+    }
+}
+
+// the members of the domain that are set, in the order of EIP-712
+fn domain_fields(domain: &Eip712Domain) -> Vec<TypedField> {
+    [
+        ("name", "string", domain.name.is_some()),
+        ("version", "string", domain.version.is_some()),
+        ("chainId", "uint256", domain.chain_id.is_some()),
+        (
+            "verifyingContract",
+            "address",
+            domain.verifying_contract.is_some(),
+        ),
+        ("salt", "bytes32", domain.salt.is_some()),
+    ]
+    .into_iter()
+    .filter(|(_, _, set)| *set)
+    .map(|(name, ty, _)| TypedField {
+        name: name.to_string(),
+        ty: ty.to_string(),
+    })
+    .collect()
+}
+
+// the members of a type encoded as `Name(type1 name1,type2 name2)`
+fn fields(root_type: &str) -> Vec<TypedField> {
+    let members = root_type
+        .split_once('(')
+        .and_then(|(_, rest)| rest.strip_suffix(')'))
+        .unwrap_or_default();
+    members
+        .split(',')
+        .filter_map(|member| member.split_once(' '))
+        .map(|(ty, name)| TypedField {
+            name: name.to_string(),
+            ty: ty.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::DOMAIN;
+    use crate::SigningMessage;
+    use alloy_core::primitives::Address;
+
+    #[test]
+    fn signing_message_payload() {
+        let message = SigningMessage {
+            app: Address::ZERO,
+            nonce: 3,
+            max_gas_price: 10,
+            data: vec![1, 2, 3].into(),
+        };
+        let typed_data = TypedData::new(&DOMAIN, message.clone());
+        assert_eq!(
+            typed_data.signing_hash(),
+            message.eip712_signing_hash(&DOMAIN)
+        );
+
+        let json = serde_json::to_value(&typed_data).unwrap();
+        assert_eq!(json["primaryType"], "SigningMessage");
+        assert_eq!(json["domain"]["chainId"], "0x539");
+        assert_eq!(json["message"]["nonce"], 3);
+        assert_eq!(
+            json["types"]["SigningMessage"],
+            serde_json::json!([
+                {"name": "app", "type": "address"},
+                {"name": "nonce", "type": "uint64"},
+                {"name": "max_gas_price", "type": "uint128"},
+                {"name": "data", "type": "bytes"},
+            ])
+        );
+        assert_eq!(json["types"]["EIP712Domain"].as_array().unwrap().len(), 4);
+
+        let parsed: TypedData<SigningMessage> = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, typed_data);
+    }
+}
@@ -0,0 +1,133 @@
+use alloy_core::primitives::U256;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+// config keys are overridden by the environment variables named after them,
+// like `TRIPA_BASE_URL` for `base_url`
+const ENV_PREFIX: &str = "TRIPA_";
+
+/// A centralized sequencer posting batches of signed transactions to a DA layer.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// The config file, whose keys can be overridden by `TRIPA_<KEY>` variables.
+    #[arg(
+        long,
+        global = true,
+        env = "TRIPA_CONFIG",
+        default_value = "config.toml"
+    )]
+    pub config: PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Serve the API and post batches (the default).
+    Serve {
+        /// Run on a local Anvil, where the sequencer is funded and gets an
+        /// InputBox of its own.
+        #[arg(long, env = "TRIPA_DEV")]
+        dev: bool,
+    },
+    /// Deploy an InputBox, paid by the sequencer key, and print its address.
+    DeployInputbox,
+    /// Send ether to the account of the sequencer key.
+    Fund {
+        /// Private key of the account paying.
+        #[arg(long, env = "TRIPA_FUND_KEY", hide_env_values = true)]
+        key: String,
+        /// How much to send, in wei.
+        #[arg(long, default_value = "30000000000000000000")]
+        amount: U256,
+    },
+    /// Serve the sequencer key to another tripa, as a signing service would.
+    Signer {
+        #[arg(long, env = "TRIPA_SIGNER_LISTEN", default_value = "127.0.0.1:5006")]
+        listen: String,
+    },
+}
+
+/// Sets the keys of `table` from the variables named after them, whether the
+/// file has the key or not. A variable for one of `integer_keys` is read as a
+/// number, and any other as a string; a value that is not a number is kept as
+/// a string, for the config to reject. Variables that name no key of the
+/// config, like `TRIPA_CONFIG`, are ignored when it is read.
+pub fn apply_env_overrides(
+    table: &mut toml::Table,
+    integer_keys: &[&str],
+    vars: impl IntoIterator<Item = (String, String)>,
+) {
+    for (name, value) in vars {
+        let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let key = key.to_lowercase();
+        let value = match value.parse::<i64>() {
+            Ok(number) if integer_keys.contains(&key.as_str()) => toml::Value::Integer(number),
+            _ => toml::Value::String(value),
+        };
+        table.insert(key, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn env_overrides_keep_types() {
+        let mut table: toml::Table = toml::from_str(
+            r#"
+            base_url = "http://127.0.0.1:8545"
+            max_nonce_gap = 16
+            "#,
+        )
+        .unwrap();
+        apply_env_overrides(
+            &mut table,
+            &["max_nonce_gap", "confirmation_depth", "chain_id"],
+            [
+                var("TRIPA_BASE_URL", "http://node:8545"),
+                var("TRIPA_DOMAIN_VERSION", "2"),
+                var("TRIPA_MAX_NONCE_GAP", "4"),
+                var("TRIPA_SNAPSHOT_PATH", "state.json"),
+                var("TRIPA_CONFIRMATION_DEPTH", "3"),
+                var("TRIPA_CHAIN_ID", "mainnet"),
+                var("HOME", "/root"),
+            ],
+        );
+        assert_eq!(table["base_url"].as_str(), Some("http://node:8545"));
+        // the key holds a string, so the number is kept as one, even though
+        // the file does not have the key
+        assert_eq!(table["domain_version"].as_str(), Some("2"));
+        assert_eq!(table["max_nonce_gap"].as_integer(), Some(4));
+        assert_eq!(table["snapshot_path"].as_str(), Some("state.json"));
+        assert_eq!(table["confirmation_depth"].as_integer(), Some(3));
+        // left for the config to turn down
+        assert_eq!(table["chain_id"].as_str(), Some("mainnet"));
+        assert!(!table.contains_key("home"));
+    }
+
+    #[test]
+    fn serve_is_the_default() {
+        let cli = Cli::try_parse_from(["tripa", "--config", "other.toml"]).unwrap();
+        assert_eq!(cli.config, PathBuf::from("other.toml"));
+        assert!(cli.command.is_none());
+
+        let cli = Cli::try_parse_from(["tripa", "serve", "--dev"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Serve { dev: true })));
+        let cli = Cli::try_parse_from(["tripa", "fund", "--key", "0x01"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Fund { .. })));
+        let cli = Cli::try_parse_from(["tripa", "signer"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::Signer { listen }) if listen == "127.0.0.1:5006"
+        ));
+    }
+}
@@ -0,0 +1,192 @@
+use super::input_box::{post_input, ADD_INPUT_GAS};
+use super::{DaBackend, Inclusion, L1Sender, Submission};
+use crate::oracle::{fetch_network_fees, NetworkFees};
+use crate::InputBox;
+use alloy_consensus::{SidecarBuilder, SimpleCoder};
+use alloy_core::primitives::{Address, Bytes};
+use alloy_provider::Provider;
+use anyhow::Error;
+use async_trait::async_trait;
+use message::Batch;
+
+// a blob is paid in full, whatever it holds
+const GAS_PER_BLOB: u128 = 131_072;
+// `SimpleCoder` fills 31 of the 32 bytes of each of the 4096 field elements
+const BATCH_BYTES_PER_BLOB: usize = 31 * 4096;
+
+/// Posts batches as EIP-4844 blobs. The blobs are carried by the transaction that
+/// calls `InputBox::addInput`, and the input holds their versioned hashes, so
+/// apps can tie each input to the blobs with the batch.
+pub struct BlobBackend {
+    sender: L1Sender,
+    input_box_address: Address,
+    // used for reading from the chain
+    provider: Box<dyn Provider<alloy_transport_http::Http<reqwest::Client>>>,
+    // the size batches are sealed at, over which the cost of the blobs and of
+    // the call is spread
+    batch_bytes: usize,
+}
+
+impl BlobBackend {
+    pub fn new(
+        sender: L1Sender,
+        input_box_address: Address,
+        provider: Box<dyn Provider<alloy_transport_http::Http<reqwest::Client>>>,
+        batch_bytes: usize,
+    ) -> Self {
+        Self {
+            sender,
+            input_box_address,
+            provider,
+            batch_bytes,
+        }
+    }
+
+    // posts `batch`, in place of the `removed` submission if there is one
+    async fn post(&self, batch: &Batch, removed: Option<&Submission>) -> Result<Submission, Error> {
+        let sidecar = SidecarBuilder::<SimpleCoder>::from_slice(&batch.to_bytes()).build()?;
+        // the input is the concatenation of the versioned hashes of the blobs
+        let versioned_hashes: Vec<_> = sidecar.versioned_hashes().collect();
+        let payload: Vec<u8> = versioned_hashes
+            .iter()
+            .flat_map(|hash| hash.to_vec())
+            .collect();
+
+        let input_contract = InputBox::new(self.input_box_address, &self.provider);
+
+        let mut tx = input_contract
+            .addInput(self.input_box_address, Bytes::from(payload.clone()))
+            .into_transaction_request();
+        tx.blob_versioned_hashes = Some(versioned_hashes);
+        tx.sidecar = Some(sidecar);
+
+        // the sender fills in the nonce and fees, blob fee included
+        let submission = post_input(
+            &self.sender,
+            &self.provider,
+            self.input_box_address,
+            tx,
+            &payload,
+            removed,
+        )
+        .await?;
+        println!(
+            "Posted batch as input {} in blob transaction {:?}",
+            submission.input_index.unwrap_or_default(),
+            submission.tx_hash
+        );
+        Ok(submission)
+    }
+}
+
+#[async_trait]
+impl DaBackend for BlobBackend {
+    async fn submit(&self, batch: &Batch) -> Result<Submission, Error> {
+        self.post(batch, None).await
+    }
+
+    async fn network_fees(&self) -> Result<NetworkFees, Error> {
+        fetch_network_fees(self.provider.as_ref(), true).await
+    }
+
+    // a full batch pays for the whole blobs holding it, and for the `addInput`
+    // call carrying them
+    fn price_per_byte(&self, fees: &NetworkFees) -> u128 {
+        let batch_bytes = self.batch_bytes.max(1);
+        let blobs = batch_bytes.div_ceil(BATCH_BYTES_PER_BLOB) as u128;
+        let blob_cost = blobs * GAS_PER_BLOB * fees.blob_base_fee.unwrap_or_default();
+        let call_cost = ADD_INPUT_GAS * (fees.base_fee + fees.priority_fee);
+        (blob_cost + call_cost).div_ceil(batch_bytes as u128)
+    }
+
+    async fn inclusion(&self, submission: &Submission) -> Result<Inclusion, Error> {
+        self.sender.inclusion(submission.tx_hash).await
+    }
+
+    // at the nonce of the removed transaction, which may still be mined again
+    async fn resubmit(&self, batch: &Batch, submission: &Submission) -> Result<Submission, Error> {
+        self.post(batch, Some(submission)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::da::input_box::find_input;
+    use crate::da::RetryPolicy;
+    use alloy_network::EthereumSigner;
+    use alloy_node_bindings::Anvil;
+    use alloy_provider::ProviderBuilder;
+    use alloy_signer_wallet::LocalWallet;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn post_blob_to_cancun_anvil() {
+        let anvil = Anvil::new()
+            .args(["--hardfork", "cancun"])
+            .try_spawn()
+            .expect("Anvil not working");
+        let signer: LocalWallet = anvil.keys()[0].clone().into();
+        let provider = ProviderBuilder::new()
+            .with_recommended_fillers()
+            .signer(EthereumSigner::from(signer.clone()))
+            .on_http(anvil.endpoint().parse().unwrap());
+        let input_box_address = InputBox::deploy_builder(provider.clone())
+            .from(signer.address())
+            .deploy()
+            .await
+            .unwrap();
+
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            resubmit_after: Duration::from_secs(10),
+            fee_bump_percent: 12,
+        };
+        let backend = BlobBackend::new(
+            L1Sender::new(&anvil.endpoint(), signer.clone(), policy).unwrap(),
+            input_box_address,
+            Box::new(ProviderBuilder::new().on_http(anvil.endpoint().parse().unwrap())),
+            120_000,
+        );
+        let fees = backend.network_fees().await.unwrap();
+        let blob_base_fee = fees.blob_base_fee.unwrap();
+        // a batch of 120000 bytes takes a whole blob
+        let blob_cost = GAS_PER_BLOB * blob_base_fee;
+        assert!(backend.price_per_byte(&fees) * 120_000 >= blob_cost);
+
+        let batch = Batch {
+            sequencer_payment_address: signer.address(),
+            txs: vec![],
+        };
+        let submission = backend.submit(&batch).await.unwrap();
+        assert!(matches!(
+            backend.inclusion(&submission).await.unwrap(),
+            Inclusion::Included { .. }
+        ));
+
+        let receipt = provider
+            .get_transaction_receipt(submission.tx_hash)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(receipt.blob_gas_used.unwrap_or_default() > 0);
+
+        // the input holds the versioned hash of the blob
+        let expected = SidecarBuilder::<SimpleCoder>::from_slice(&batch.to_bytes())
+            .build()
+            .unwrap()
+            .versioned_hashes()
+            .next()
+            .unwrap();
+        let advance = find_input(
+            &receipt,
+            input_box_address,
+            input_box_address,
+            signer.address(),
+            expected.as_slice(),
+        )
+        .unwrap();
+        assert_eq!(submission.input_index, Some(advance.index.to()));
+    }
+}
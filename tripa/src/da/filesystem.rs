@@ -0,0 +1,332 @@
+use super::{DaBackend, Inclusion, Submission};
+use crate::oracle::NetworkFees;
+use alloy_core::primitives::{keccak256, Address, Bytes, B256};
+use alloy_core::sol_types::Eip712Domain;
+use anyhow::Error;
+use async_trait::async_trait;
+use axum::{
+    extract::{Path as UrlPath, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use message::{Batch, WalletState};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tokio::sync::Mutex;
+
+/// Writes each batch to `<dir>/<index>.batch`, with indices starting at zero.
+/// Meant for development, to run apps without an Ethereum node.
+pub struct FilesystemBackend {
+    dir: PathBuf,
+    next_index: Mutex<u64>,
+}
+
+impl FilesystemBackend {
+    /// Opens (or creates) `dir`, continuing after the batches already in it.
+    pub fn new(dir: &Path) -> Result<Self, Error> {
+        fs::create_dir_all(dir)?;
+        let mut next_index = 0;
+        while batch_path(dir, next_index).exists() {
+            next_index += 1;
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            next_index: Mutex::new(next_index),
+        })
+    }
+}
+
+fn batch_path(dir: &Path, index: u64) -> PathBuf {
+    dir.join(format!("{:016}.batch", index))
+}
+
+/// Rebuilds the wallet state from the batches written to `dir`, verifying
+/// them under `domain` like the payment app does. Written batches are final,
+/// so all of them count; returns how many there are.
+pub async fn replay(dir: &Path, domain: Eip712Domain) -> Result<(WalletState, u64), Error> {
+    let mut wallet_state = WalletState::new(domain);
+    let mut index = 0;
+    loop {
+        let bytes = match tokio::fs::read(batch_path(dir, index)).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => break,
+            Err(e) => return Err(e.into()),
+        };
+        match wallet_state.verify_raw_batch(&bytes) {
+            Ok(txs) => println!("Replayed batch {} with {} transactions", index, txs.len()),
+            Err(e) => println!("Skipping batch {}: {}", index, e),
+        }
+        index += 1;
+    }
+    Ok((wallet_state, index))
+}
+
+#[async_trait]
+impl DaBackend for FilesystemBackend {
+    // the submission is identified by the hash of the batch, and the index
+    // of the batch takes the place of the block number
+    async fn submit(&self, batch: &Batch) -> Result<Submission, Error> {
+        let bytes = batch.to_bytes();
+        let mut next_index = self.next_index.lock().await;
+        let path = batch_path(&self.dir, *next_index);
+        let tmp_path = path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, &bytes).await?;
+        tokio::fs::File::open(&tmp_path).await?.sync_all().await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+        println!("Wrote batch to {:?}", path);
+
+        let submission = Submission {
+            tx_hash: keccak256(&bytes),
+            block_number: Some(*next_index),
+            input_index: Some(*next_index),
+            nonce: None,
+        };
+        *next_index += 1;
+        Ok(submission)
+    }
+
+    // there is no chain behind the backend
+    async fn network_fees(&self) -> Result<NetworkFees, Error> {
+        Ok(NetworkFees::default())
+    }
+
+    // writing to disk is free
+    fn price_per_byte(&self, _fees: &NetworkFees) -> u128 {
+        0
+    }
+
+    // written batches are final, there are no reorgs on disk
+    async fn inclusion(&self, submission: &Submission) -> Result<Inclusion, Error> {
+        let Some(index) = submission.block_number else {
+            return Ok(Inclusion::Missing);
+        };
+        match tokio::fs::read(batch_path(&self.dir, index)).await {
+            Ok(bytes) if keccak256(&bytes) == submission.tx_hash => Ok(Inclusion::Included {
+                block_number: index,
+                confirmations: u64::MAX,
+            }),
+            _ => Ok(Inclusion::Missing),
+        }
+    }
+}
+
+/// Serves the batches written by `FilesystemBackend` as Cartesi rollup inputs,
+/// so that the advance loop of an app can be driven without a node.
+#[derive(Clone)]
+pub struct InputServer {
+    pub dir: PathBuf,
+    pub chain_id: u64,
+    pub app_contract: Address,
+    pub msg_sender: Address,
+    // next input handed out by `POST /finish`
+    pub next_input: Arc<Mutex<u64>>,
+}
+
+impl InputServer {
+    pub fn router(self) -> Router {
+        Router::new()
+            // `POST /finish` gets the next input, like the rollup http server
+            .route("/finish", post(finish))
+            // `GET /inputs/:index` gets a given input
+            .route("/inputs/:index", get(get_input))
+            .with_state(self)
+    }
+
+    async fn advance_request(&self, index: u64) -> Option<RollupRequest> {
+        let path = batch_path(&self.dir, index);
+        let payload = tokio::fs::read(&path).await.ok()?;
+        let block_timestamp = tokio::fs::metadata(&path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |elapsed| elapsed.as_secs());
+        Some(RollupRequest {
+            request_type: "advance_state".to_string(),
+            data: AdvanceRequest {
+                metadata: AdvanceMetadata {
+                    chain_id: self.chain_id,
+                    app_contract: self.app_contract,
+                    msg_sender: self.msg_sender,
+                    block_number: index,
+                    block_timestamp,
+                    prev_randao: B256::ZERO,
+                    input_index: index,
+                },
+                payload: payload.into(),
+            },
+        })
+    }
+}
+
+// the request handed to the app, as in the rollup http api
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct RollupRequest {
+    pub request_type: String,
+    pub data: AdvanceRequest,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct AdvanceRequest {
+    pub metadata: AdvanceMetadata,
+    pub payload: Bytes,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct AdvanceMetadata {
+    pub chain_id: u64,
+    pub app_contract: Address,
+    pub msg_sender: Address,
+    pub block_number: u64,
+    pub block_timestamp: u64,
+    pub prev_randao: B256,
+    pub input_index: u64,
+}
+
+// the status of the previous request, sent by the app
+#[derive(Deserialize, Debug)]
+struct Finish {
+    #[allow(dead_code)]
+    status: String,
+}
+
+async fn finish(
+    State(server): State<InputServer>,
+    Json(_finish): Json<Finish>,
+) -> Result<(StatusCode, Json<RollupRequest>), StatusCode> {
+    let mut next_input = server.next_input.lock().await;
+    match server.advance_request(*next_input).await {
+        Some(request) => {
+            *next_input += 1;
+            Ok((StatusCode::OK, Json(request)))
+        }
+        // no new input yet, the app should try again
+        None => Err(StatusCode::ACCEPTED),
+    }
+}
+
+async fn get_input(
+    State(server): State<InputServer>,
+    UrlPath(index): UrlPath<u64>,
+) -> Result<(StatusCode, Json<RollupRequest>), StatusCode> {
+    match server.advance_request(index).await {
+        Some(request) => Ok((StatusCode::OK, Json(request))),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::temp_path as temp_dir;
+    use axum::{
+        body::Body,
+        http::{self, Request},
+    };
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    fn batch(byte: u8) -> Batch {
+        Batch {
+            sequencer_payment_address: Address::repeat_byte(byte),
+            txs: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn indices_are_monotonic() {
+        let dir = temp_dir("fs-backend");
+        let backend = FilesystemBackend::new(&dir).unwrap();
+        let first = backend.submit(&batch(1)).await.unwrap();
+        let second = backend.submit(&batch(2)).await.unwrap();
+        assert_eq!(first.block_number, Some(0));
+        assert_eq!(second.block_number, Some(1));
+        assert_eq!(
+            backend.inclusion(&second).await.unwrap(),
+            Inclusion::Included {
+                block_number: 1,
+                confirmations: u64::MAX
+            }
+        );
+        // a batch overwritten on disk is not the one submitted
+        fs::write(batch_path(&dir, 0), batch(4).to_bytes()).unwrap();
+        assert_eq!(backend.inclusion(&first).await.unwrap(), Inclusion::Missing);
+
+        // a new backend continues after the batches already written
+        let backend = FilesystemBackend::new(&dir).unwrap();
+        let third = backend.submit(&batch(3)).await.unwrap();
+        assert_eq!(third.block_number, Some(2));
+
+        // every batch on disk counts, even one the app cannot read
+        fs::write(batch_path(&dir, 3), [0xff]).unwrap();
+        let (_, batches) = replay(&dir, crate::DOMAIN).await.unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(batches, 4);
+    }
+
+    async fn call(router: Router, request: Request<Body>) -> (StatusCode, Option<RollupRequest>) {
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).ok())
+    }
+
+    fn finish_request() -> Request<Body> {
+        Request::builder()
+            .uri("/finish")
+            .method(http::Method::POST)
+            .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(r#"{"status":"accept"}"#))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn serve_inputs() {
+        let dir = temp_dir("fs-inputs");
+        let backend = FilesystemBackend::new(&dir).unwrap();
+        backend.submit(&batch(1)).await.unwrap();
+        let router = InputServer {
+            dir: dir.clone(),
+            chain_id: 1337,
+            app_contract: Address::ZERO,
+            msg_sender: Address::repeat_byte(1),
+            next_input: Arc::new(Mutex::new(0)),
+        }
+        .router();
+
+        let (status, request) = call(router.clone(), finish_request()).await;
+        assert_eq!(status, StatusCode::OK);
+        let request = request.unwrap();
+        assert_eq!(request.request_type, "advance_state");
+        assert_eq!(request.data.metadata.input_index, 0);
+        assert_eq!(Batch::from_bytes(&request.data.payload).unwrap(), batch(1));
+
+        // no more inputs for now
+        let (status, _) = call(router.clone(), finish_request()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+
+        let get = Request::builder()
+            .uri("/inputs/0")
+            .body(Body::empty())
+            .unwrap();
+        let (status, request) = call(router.clone(), get).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            request.unwrap().data.metadata.msg_sender,
+            Address::repeat_byte(1)
+        );
+
+        let missing = Request::builder()
+            .uri("/inputs/1")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _) = call(router, missing).await;
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,341 @@
+use super::sender::NonceTaken;
+use super::{with_backoff, DaBackend, Inclusion, L1Sender, Submission};
+use crate::oracle::{fetch_network_fees, NetworkFees};
+use crate::{EvmAdvanceCall, InputBox};
+use alloy_core::primitives::{Address, Bytes};
+use alloy_core::sol_types::SolCall;
+use alloy_provider::Provider;
+use alloy_rpc_types::{TransactionReceipt, TransactionRequest};
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use message::Batch;
+
+const CALLDATA_GAS_PER_BYTE: u128 = 16;
+// gas of a transaction calling `addInput`, leaving out the payload: the base
+// cost, storing the input hash and emitting the event, with room to spare
+pub(super) const ADD_INPUT_GAS: u128 = 100_000;
+
+/// Posts batches as calldata, through `InputBox::addInput`.
+pub struct InputBoxBackend {
+    sender: L1Sender,
+    input_box_address: Address,
+    // used for reading from the chain
+    provider: Box<dyn Provider<alloy_transport_http::Http<reqwest::Client>>>,
+    // the size batches are sealed at, over which the cost of the call is spread
+    batch_bytes: usize,
+}
+
+impl InputBoxBackend {
+    pub fn new(
+        sender: L1Sender,
+        input_box_address: Address,
+        provider: Box<dyn Provider<alloy_transport_http::Http<reqwest::Client>>>,
+        batch_bytes: usize,
+    ) -> Self {
+        Self {
+            sender,
+            input_box_address,
+            provider,
+            batch_bytes,
+        }
+    }
+
+    // posts `batch`, in place of the `removed` submission if there is one
+    async fn post(&self, batch: &Batch, removed: Option<&Submission>) -> Result<Submission, Error> {
+        let input_contract = InputBox::new(self.input_box_address, &self.provider);
+
+        // the sender fills in the nonce and fees, and retries until it is included
+        let payload = batch.to_bytes();
+        let tx = input_contract
+            .addInput(self.input_box_address, Bytes::copy_from_slice(&payload))
+            .into_transaction_request();
+
+        let submission = post_input(
+            &self.sender,
+            &self.provider,
+            self.input_box_address,
+            tx,
+            &payload,
+            removed,
+        )
+        .await?;
+        println!(
+            "Posted batch as input {} in transaction {:?}",
+            submission.input_index.unwrap_or_default(),
+            submission.tx_hash
+        );
+        Ok(submission)
+    }
+}
+
+#[async_trait]
+impl DaBackend for InputBoxBackend {
+    async fn submit(&self, batch: &Batch) -> Result<Submission, Error> {
+        self.post(batch, None).await
+    }
+
+    async fn network_fees(&self) -> Result<NetworkFees, Error> {
+        fetch_network_fees(self.provider.as_ref(), false).await
+    }
+
+    // calldata costs 16 gas per non-zero byte, assume the worst case, and the
+    // call itself is shared by the bytes of a full batch
+    fn price_per_byte(&self, fees: &NetworkFees) -> u128 {
+        let gas_price = fees.base_fee + fees.priority_fee;
+        CALLDATA_GAS_PER_BYTE * gas_price
+            + (ADD_INPUT_GAS * gas_price).div_ceil(self.batch_bytes.max(1) as u128)
+    }
+
+    async fn inclusion(&self, submission: &Submission) -> Result<Inclusion, Error> {
+        self.sender.inclusion(submission.tx_hash).await
+    }
+
+    // at the nonce of the removed transaction, which may still be mined again
+    async fn resubmit(&self, batch: &Batch, submission: &Submission) -> Result<Submission, Error> {
+        self.post(batch, Some(submission)).await
+    }
+}
+
+/// Sends `tx`, which adds `payload` to the inbox of the app at the InputBox
+/// address, and tells where the input landed. With the submission of a version
+/// a reorg `removed`, `tx` is sent at its nonce, so that the two cannot both
+/// land. If the nonce of `tx` was used by a transaction the sender lost track
+/// of, the input is looked for in the logs, from the block the removed version
+/// was in if any; when it is not there, the nonce went to another transaction,
+/// so `tx` is sent again with a new one.
+pub(super) async fn post_input<P>(
+    sender: &L1Sender,
+    provider: P,
+    input_box_address: Address,
+    tx: TransactionRequest,
+    payload: &[u8],
+    removed: Option<&Submission>,
+) -> Result<Submission, Error>
+where
+    P: Provider<alloy_transport_http::Http<reqwest::Client>>,
+{
+    let input_contract = InputBox::new(input_box_address, provider);
+    let mut resend_at = removed.and_then(|submission| submission.nonce);
+    // the removed version may have been mined again before it is resent
+    let removed_block = removed.and_then(|submission| submission.block_number);
+    loop {
+        let sent = match resend_at.take() {
+            Some(nonce) => sender
+                .resend(tx.clone(), nonce)
+                .await
+                .map(|receipt| (receipt, nonce)),
+            None => sender.send(tx.clone()).await,
+        };
+        let error = match sent {
+            Ok((receipt, nonce)) => {
+                if !receipt.status() {
+                    return Err(anyhow!("transaction {} reverted", receipt.transaction_hash));
+                }
+                // the InputBox address doubles as the app, as in `addInput`
+                let advance = find_input(
+                    &receipt,
+                    input_box_address,
+                    input_box_address,
+                    sender.address(),
+                    payload,
+                )?;
+                return Ok(Submission {
+                    tx_hash: receipt.transaction_hash,
+                    block_number: receipt.block_number,
+                    input_index: Some(advance.index.to()),
+                    nonce: Some(nonce),
+                });
+            }
+            Err(e) => e,
+        };
+        let Some(taken) = error.downcast_ref::<NonceTaken>() else {
+            return Err(error);
+        };
+
+        let policy = sender.policy();
+        let logs = with_backoff(&policy, "look for the input", || async {
+            Ok(input_contract
+                .InputAdded_filter()
+                .topic1(input_box_address.into_word())
+                .from_block(
+                    removed_block.map_or(taken.from_block, |block| block.min(taken.from_block)),
+                )
+                .query()
+                .await?)
+        })
+        .await;
+        let landed = logs.iter().find(|(event, _)| {
+            check_input(event, input_box_address, sender.address(), payload).is_ok()
+        });
+        match landed {
+            Some((event, log)) => {
+                println!(
+                    "Found input {} sent with nonce {}",
+                    event.index, taken.nonce
+                );
+                return Ok(Submission {
+                    tx_hash: log.transaction_hash.unwrap_or_default(),
+                    block_number: log.block_number,
+                    input_index: Some(event.index.to()),
+                    nonce: Some(taken.nonce),
+                });
+            }
+            None => println!("{}, sending the input again", taken),
+        }
+    }
+}
+
+/// Finds the input that `receipt` added to the inbox of `app`, and checks that
+/// it was sent by `msg_sender` and carries `payload`. Only the logs of the
+/// receipt are looked at, so inputs sent by others do not get in the way.
+pub(super) fn find_input(
+    receipt: &TransactionReceipt,
+    input_box_address: Address,
+    app: Address,
+    msg_sender: Address,
+    payload: &[u8],
+) -> Result<EvmAdvanceCall, Error> {
+    let event = receipt
+        .inner
+        .logs()
+        .iter()
+        .filter(|log| log.address() == input_box_address)
+        .find_map(|log| log.log_decode::<InputBox::InputAdded>().ok())
+        .ok_or(anyhow!(
+            "transaction {} did not add an input",
+            receipt.transaction_hash
+        ))?;
+    check_input(&event.inner.data, app, msg_sender, payload)
+}
+
+// decodes the `EvmAdvance` call held by the event, which must match what was sent
+fn check_input(
+    event: &InputBox::InputAdded,
+    app: Address,
+    msg_sender: Address,
+    payload: &[u8],
+) -> Result<EvmAdvanceCall, Error> {
+    if event.appContract != app {
+        return Err(anyhow!(
+            "input {} was added to {} instead of {}",
+            event.index,
+            event.appContract,
+            app
+        ));
+    }
+    // the input starts with the selector of `EvmAdvance`
+    let advance = EvmAdvanceCall::abi_decode(&event.input, true)
+        .map_err(|e| anyhow!("input {} is not an EvmAdvance call: {}", event.index, e))?;
+    if advance.msgSender != msg_sender {
+        return Err(anyhow!(
+            "input {} was sent by {} instead of {}",
+            event.index,
+            advance.msgSender,
+            msg_sender
+        ));
+    }
+    if advance.payload.as_ref() != payload {
+        return Err(anyhow!("input {} does not carry the batch", event.index));
+    }
+    Ok(advance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::da::RetryPolicy;
+    use alloy_core::primitives::{address, U256};
+    use alloy_network::EthereumSigner;
+    use alloy_node_bindings::Anvil;
+    use alloy_provider::ProviderBuilder;
+    use alloy_signer_wallet::LocalWallet;
+    use std::time::Duration;
+
+    fn input_added(app: Address, msg_sender: Address, payload: &[u8]) -> InputBox::InputAdded {
+        let input = EvmAdvanceCall {
+            chainId: U256::from(1337),
+            appContract: app,
+            msgSender: msg_sender,
+            blockNumber: U256::from(1),
+            blockTimestamp: U256::from(2),
+            prevRandao: U256::from(3),
+            index: U256::from(7),
+            payload: Bytes::copy_from_slice(payload),
+        }
+        .abi_encode();
+        InputBox::InputAdded {
+            appContract: app,
+            index: U256::from(7),
+            input: input.into(),
+        }
+    }
+
+    #[test]
+    fn input_must_match_the_batch() {
+        let app = address!("0000000000000000000000000000000000000003");
+        let sequencer = address!("0000000000000000000000000000000000000099");
+        let batch = Batch {
+            sequencer_payment_address: sequencer,
+            txs: vec![],
+        };
+        let payload = batch.to_bytes();
+        let event = input_added(app, sequencer, &payload);
+
+        let advance = check_input(&event, app, sequencer, &payload).unwrap();
+        assert_eq!(advance.index, U256::from(7));
+        assert!(check_input(&event, Address::ZERO, sequencer, &payload).is_err());
+        assert!(check_input(&event, app, Address::ZERO, &payload).is_err());
+        assert!(check_input(&event, app, sequencer, b"another batch").is_err());
+
+        // an input that is not an `EvmAdvance` call is an error, not a panic
+        let mut event = event;
+        event.input = Bytes::from_static(b"garbage");
+        assert!(check_input(&event, app, sequencer, &payload).is_err());
+    }
+
+    #[tokio::test]
+    async fn resubmitted_batch_lands_once() {
+        let anvil = Anvil::new().try_spawn().expect("Anvil not working");
+        let signer: LocalWallet = anvil.keys()[0].clone().into();
+        let provider = ProviderBuilder::new()
+            .with_recommended_fillers()
+            .signer(EthereumSigner::from(signer.clone()))
+            .on_http(anvil.endpoint().parse().unwrap());
+        let input_box_address = InputBox::deploy_builder(provider.clone())
+            .from(signer.address())
+            .deploy()
+            .await
+            .unwrap();
+
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            resubmit_after: Duration::from_secs(10),
+            fee_bump_percent: 12,
+        };
+        let backend = InputBoxBackend::new(
+            L1Sender::new(&anvil.endpoint(), signer.clone(), policy).unwrap(),
+            input_box_address,
+            Box::new(ProviderBuilder::new().on_http(anvil.endpoint().parse().unwrap())),
+            10_000,
+        );
+        let batch = Batch {
+            sequencer_payment_address: signer.address(),
+            txs: vec![],
+        };
+        let submission = backend.submit(&batch).await.unwrap();
+        assert!(submission.nonce.is_some());
+
+        // as if the removed transaction was mined again before it was resent:
+        // its nonce is taken, and the input it added is found
+        let resubmitted = backend.resubmit(&batch, &submission).await.unwrap();
+        assert_eq!(resubmitted.tx_hash, submission.tx_hash);
+        assert_eq!(resubmitted.input_index, submission.input_index);
+        let inputs = InputBox::new(input_box_address, provider)
+            .getNumberOfInputs(input_box_address)
+            .call()
+            .await
+            .unwrap();
+        assert_eq!(inputs._0, U256::from(1));
+    }
+}
@@ -0,0 +1,76 @@
+use crate::oracle::NetworkFees;
+use alloy_core::primitives::B256;
+use anyhow::Error;
+use async_trait::async_trait;
+use message::Batch;
+use serde::{Deserialize, Serialize};
+
+mod blob;
+mod filesystem;
+mod input_box;
+mod sender;
+pub use blob::BlobBackend;
+pub use filesystem::{replay as replay_filesystem, FilesystemBackend, InputServer};
+pub use input_box::InputBoxBackend;
+pub use sender::{with_backoff, L1Sender, RetryPolicy};
+
+/// Where a batch was posted.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Submission {
+    pub tx_hash: B256,
+    pub block_number: Option<u64>,
+    // index of the input holding the batch, in the inbox of the app
+    pub input_index: Option<u64>,
+    // nonce of the transaction that posted it, when it was sent to L1
+    #[serde(default)]
+    pub nonce: Option<u64>,
+}
+
+/// Where a submitted batch stands on the DA layer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Inclusion {
+    /// Not included, or not anymore after a reorg.
+    Missing,
+    /// Included in `block_number`, with `confirmations` blocks built on it
+    /// (counting its own).
+    Included {
+        block_number: u64,
+        confirmations: u64,
+    },
+}
+
+/// A data availability layer batches are posted to.
+#[async_trait]
+pub trait DaBackend: Send + Sync {
+    /// Posts the batch, returning once it has been included. Transient errors
+    /// are retried; an error means the batch did not make it.
+    async fn submit(&self, batch: &Batch) -> Result<Submission, Error>;
+
+    /// Current fees of the chain the backend posts to.
+    async fn network_fees(&self) -> Result<NetworkFees, Error>;
+
+    /// Price of posting one byte of batch data under `fees`, in wei.
+    fn price_per_byte(&self, fees: &NetworkFees) -> u128;
+
+    /// Tells where a previously submitted batch stands.
+    async fn inclusion(&self, submission: &Submission) -> Result<Inclusion, Error>;
+
+    /// Posts a batch again, after a reorg removed its `submission`. The removed
+    /// one may still come back, so the two must never both be included.
+    async fn resubmit(&self, batch: &Batch, _submission: &Submission) -> Result<Submission, Error> {
+        self.submit(batch).await
+    }
+}
+
+/// Which backend tripa posts batches to.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DaBackendKind {
+    /// Batches are sent as calldata to the InputBox.
+    #[default]
+    InputBox,
+    /// Batches are sent as EIP-4844 blobs, referenced by an InputBox input.
+    Blob,
+    /// Batches are written to a local directory, for development.
+    Filesystem,
+}
@@ -0,0 +1,342 @@
+use super::Inclusion;
+use crate::oracle::{fetch_network_fees, NetworkFees};
+use alloy_core::primitives::{Address, B256};
+use alloy_network::{EthereumSigner, TxSigner};
+use alloy_provider::{Provider, ProviderBuilder};
+use alloy_rpc_types::{TransactionReceipt, TransactionRequest};
+use alloy_signer::Signature;
+use anyhow::Error;
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+// how often the receipts of a pending transaction are looked for
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The nonce of a transaction was used by one whose hash the sender does not
+/// know: a version it could not tell was broadcast, or a transaction sent by
+/// someone else. Which one it was is told by what landed since `from_block`.
+#[derive(Debug)]
+pub struct NonceTaken {
+    pub nonce: u64,
+    pub from_block: u64,
+}
+
+impl fmt::Display for NonceTaken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "nonce {} was used by an unknown transaction", self.nonce)
+    }
+}
+
+impl std::error::Error for NonceTaken {}
+
+/// How hard transactions are pushed through to L1.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    // wait after a failed RPC call, doubled after each failure in a row
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    // how long a transaction can be pending before it is replaced
+    pub resubmit_after: Duration,
+    // how much the fees are raised when replacing a transaction
+    pub fee_bump_percent: u64,
+}
+
+impl RetryPolicy {
+    pub fn backoff(&self, failures: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(failures))
+            .min(self.max_backoff)
+    }
+}
+
+/// Retries `f` until it succeeds, waiting longer after each failure.
+pub async fn with_backoff<T, F, Fut>(policy: &RetryPolicy, what: &str, mut f: F) -> T
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let mut failures = 0;
+    loop {
+        match f().await {
+            Ok(value) => return value,
+            Err(e) => {
+                let wait = policy.backoff(failures);
+                println!("Could not {}, retrying in {:?}: {}", what, wait, e);
+                tokio::time::sleep(wait).await;
+                failures = failures.saturating_add(1);
+            }
+        }
+    }
+}
+
+fn bump(fee: Option<u128>, percent: u64) -> Option<u128> {
+    // always raise by at least one wei, so that tiny fees go up too
+    fee.map(|fee| (fee.saturating_mul(100 + percent as u128) / 100).max(fee + 1))
+}
+
+fn bump_fees(tx: &mut TransactionRequest, percent: u64) {
+    tx.max_priority_fee_per_gas = bump(tx.max_priority_fee_per_gas, percent);
+    tx.max_fee_per_gas = bump(tx.max_fee_per_gas, percent);
+    tx.max_fee_per_blob_gas = bump(tx.max_fee_per_blob_gas, percent);
+}
+
+fn set_fees(tx: &mut TransactionRequest, fees: &NetworkFees) {
+    tx.max_priority_fee_per_gas = Some(fees.priority_fee);
+    // leave room for the base fee to rise before the transaction is included
+    tx.max_fee_per_gas = Some(2 * fees.base_fee + fees.priority_fee);
+    if let Some(blob_base_fee) = fees.blob_base_fee {
+        tx.max_fee_per_blob_gas = Some(2 * blob_base_fee.max(1));
+    }
+}
+
+/// Sends transactions from the sequencer account, one at a time and in nonce order.
+pub struct L1Sender {
+    signer_address: Address,
+    // signs the transactions, and reads their receipts
+    provider: Box<dyn Provider<alloy_transport_http::Http<reqwest::Client>>>,
+    policy: RetryPolicy,
+    // next nonce of the sequencer account, fetched from the chain when unknown
+    nonce: Mutex<Option<u64>>,
+}
+
+impl L1Sender {
+    pub fn new<S>(base_url: &str, signer: S, policy: RetryPolicy) -> Result<Self, Error>
+    where
+        S: TxSigner<Signature> + Send + Sync + 'static,
+    {
+        let signer_address = signer.address();
+        let provider = ProviderBuilder::new()
+            .with_recommended_fillers()
+            .signer(EthereumSigner::from(signer))
+            .on_http(base_url.parse()?);
+        Ok(Self {
+            signer_address,
+            provider: Box::new(provider),
+            policy,
+            nonce: Mutex::new(None),
+        })
+    }
+
+    /// The sequencer account the transactions are sent from.
+    pub fn address(&self) -> Address {
+        self.signer_address
+    }
+
+    pub fn policy(&self) -> RetryPolicy {
+        self.policy
+    }
+
+    /// Sends `tx` and waits for it to be included, replacing it with higher fees
+    /// for as long as it is stuck. Every version has the same nonce, so at most
+    /// one of them is included, and the sender never gives up on it: a version
+    /// may be in the mempool even when broadcasting it seemed to fail. Returns
+    /// the receipt with the nonce, and fails with `NonceTaken` if the nonce was
+    /// used by a transaction whose hash is not known.
+    pub async fn send(&self, tx: TransactionRequest) -> Result<(TransactionReceipt, u64), Error> {
+        let mut next_nonce = self.nonce.lock().await;
+        let nonce = match *next_nonce {
+            Some(nonce) => nonce,
+            None => self.pending_nonce().await,
+        };
+        // kept until the chain moves past it, as any version may still land
+        *next_nonce = Some(nonce);
+        let receipt = self.send_at(&mut next_nonce, tx, nonce).await?;
+        Ok((receipt, nonce))
+    }
+
+    /// Sends `tx` again at `nonce`, the nonce of a version a reorg removed from
+    /// the chain, with fees above the current ones. The removed version may be
+    /// mined again, but it can only replace the new one, never land with it.
+    /// Fails with `NonceTaken` when it is the removed version that was mined.
+    pub async fn resend(
+        &self,
+        mut tx: TransactionRequest,
+        nonce: u64,
+    ) -> Result<TransactionReceipt, Error> {
+        let mut next_nonce = self.nonce.lock().await;
+        let with_blob = tx.sidecar.is_some();
+        let fees = with_backoff(&self.policy, "get the network fees", || {
+            fetch_network_fees(self.provider.as_ref(), with_blob)
+        })
+        .await;
+        // the first broadcast bumps them
+        set_fees(&mut tx, &fees);
+        self.send_at(&mut next_nonce, tx, nonce).await
+    }
+
+    // sends versions of `tx` at `nonce` until one of them is included
+    async fn send_at(
+        &self,
+        next_nonce: &mut Option<u64>,
+        mut tx: TransactionRequest,
+        nonce: u64,
+    ) -> Result<TransactionReceipt, Error> {
+        let from_block = self.block_number().await;
+        tx.nonce = Some(nonce);
+        let with_blob = tx.sidecar.is_some();
+        // the blob pool only accepts replacements that double the fees
+        let bump_percent = match with_blob {
+            true => self.policy.fee_bump_percent.max(100),
+            false => self.policy.fee_bump_percent,
+        };
+
+        // hashes of every version sent, any of them may end up included
+        let mut sent: Vec<B256> = vec![];
+        let mut failures = 0;
+        loop {
+            let wait = match self.broadcast(&mut tx, with_blob, bump_percent).await {
+                Ok(hash) => {
+                    println!("Sent transaction {} with nonce {}", hash, nonce);
+                    sent.push(hash);
+                    failures = 0;
+                    self.policy.resubmit_after
+                }
+                Err(e) => {
+                    let wait = self.policy.backoff(failures);
+                    println!(
+                        "Could not send transaction with nonce {}, retrying in {:?}: {}",
+                        nonce, wait, e
+                    );
+                    failures = failures.saturating_add(1);
+                    wait
+                }
+            };
+
+            let deadline = Instant::now() + wait;
+            loop {
+                if let Some(receipt) = self.find_receipt(&sent).await {
+                    *next_nonce = Some(next_nonce.unwrap_or_default().max(nonce + 1));
+                    return Ok(receipt);
+                }
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    break;
+                }
+                tokio::time::sleep(left.min(POLL_INTERVAL)).await;
+            }
+
+            if self.account_nonce().await > nonce {
+                // look once more, the receipt may have shown up in the meantime
+                if let Some(receipt) = self.find_receipt(&sent).await {
+                    *next_nonce = Some(next_nonce.unwrap_or_default().max(nonce + 1));
+                    return Ok(receipt);
+                }
+                *next_nonce = None;
+                return Err(NonceTaken { nonce, from_block }.into());
+            }
+            if !sent.is_empty() {
+                println!(
+                    "Transaction with nonce {} is stuck, replacing it with higher fees",
+                    nonce
+                );
+            }
+        }
+    }
+
+    // broadcasts a version of `tx`. the first one gets the current fees, and
+    // each one after it higher fees, so that it can replace the ones before
+    async fn broadcast(
+        &self,
+        tx: &mut TransactionRequest,
+        with_blob: bool,
+        bump_percent: u64,
+    ) -> Result<B256, Error> {
+        if tx.max_fee_per_gas.is_some() {
+            bump_fees(tx, bump_percent);
+        } else {
+            let fees = fetch_network_fees(self.provider.as_ref(), with_blob).await?;
+            set_fees(tx, &fees);
+        }
+        let pending = self.provider.send_transaction(tx.clone()).await?;
+        Ok(*pending.tx_hash())
+    }
+
+    /// Tells where a sent transaction stands. A reverted one did not do
+    /// what it was sent for, so it counts as missing.
+    pub async fn inclusion(&self, tx_hash: B256) -> Result<Inclusion, Error> {
+        let Some(receipt) = self.provider.get_transaction_receipt(tx_hash).await? else {
+            return Ok(Inclusion::Missing);
+        };
+        let (true, Some(block_number)) = (receipt.status(), receipt.block_number) else {
+            return Ok(Inclusion::Missing);
+        };
+        let head = self.provider.get_block_number().await?;
+        Ok(Inclusion::Included {
+            block_number,
+            confirmations: (head + 1).saturating_sub(block_number),
+        })
+    }
+
+    async fn account_nonce(&self) -> u64 {
+        with_backoff(&self.policy, "get the sequencer nonce", || async {
+            Ok(self
+                .provider
+                .get_transaction_count(self.signer_address)
+                .await?)
+        })
+        .await
+    }
+
+    // counts the transactions in the mempool too, whose nonces are taken
+    async fn pending_nonce(&self) -> u64 {
+        with_backoff(&self.policy, "get the sequencer nonce", || async {
+            Ok(self
+                .provider
+                .get_transaction_count(self.signer_address)
+                .pending()
+                .await?)
+        })
+        .await
+    }
+
+    async fn block_number(&self) -> u64 {
+        with_backoff(&self.policy, "get the block number", || async {
+            Ok(self.provider.get_block_number().await?)
+        })
+        .await
+    }
+
+    // the receipt of whichever version was included, if any. a receipt that
+    // could not be fetched is asked for again, not taken as missing
+    async fn find_receipt(&self, sent: &[B256]) -> Option<TransactionReceipt> {
+        for hash in sent {
+            let receipt = with_backoff(&self.policy, "get a transaction receipt", || async {
+                Ok(self.provider.get_transaction_receipt(*hash).await?)
+            })
+            .await;
+            if receipt.is_some() {
+                return receipt;
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_is_capped() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            resubmit_after: Duration::from_secs(10),
+            fee_bump_percent: 12,
+        };
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+        assert_eq!(policy.backoff(10), Duration::from_secs(1));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn bumped_fees_go_up() {
+        assert_eq!(bump(Some(100), 12), Some(112));
+        assert_eq!(bump(Some(1), 12), Some(2));
+        assert_eq!(bump(Some(0), 100), Some(1));
+        assert_eq!(bump(None, 12), None);
+    }
+}
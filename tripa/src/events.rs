@@ -0,0 +1,154 @@
+use alloy_core::primitives::{Address, B256};
+use serde::{Deserialize, Serialize};
+
+/// A transaction, as listed in the events about its batch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct TxSummary {
+    pub hash: B256,
+    pub sender: Address,
+    pub app: Address,
+}
+
+/// What `GET /events` pushes to its subscribers.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    TxAccepted {
+        #[serde(flatten)]
+        tx: TxSummary,
+        nonce: u64,
+        // index of the transaction in the open batch
+        position: usize,
+    },
+    BatchSealed {
+        txs: Vec<TxSummary>,
+    },
+    BatchSubmitted {
+        l1_tx_hash: B256,
+        input_index: Option<u64>,
+        txs: Vec<TxSummary>,
+    },
+    BatchFinalized {
+        l1_tx_hash: B256,
+        input_index: Option<u64>,
+        txs: Vec<TxSummary>,
+    },
+    BatchDropped {
+        reason: String,
+        txs: Vec<TxSummary>,
+    },
+}
+
+impl Event {
+    /// The name of the event, as sent in the `event` field of SSE.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::TxAccepted { .. } => "tx_accepted",
+            Event::BatchSealed { .. } => "batch_sealed",
+            Event::BatchSubmitted { .. } => "batch_submitted",
+            Event::BatchFinalized { .. } => "batch_finalized",
+            Event::BatchDropped { .. } => "batch_dropped",
+        }
+    }
+
+    fn txs_mut(&mut self) -> Option<&mut Vec<TxSummary>> {
+        match self {
+            Event::TxAccepted { .. } => None,
+            Event::BatchSealed { txs }
+            | Event::BatchSubmitted { txs, .. }
+            | Event::BatchFinalized { txs, .. }
+            | Event::BatchDropped { txs, .. } => Some(txs),
+        }
+    }
+}
+
+/// Which events a subscriber wants, read from the query of `GET /events`.
+/// Batch events only list the transactions that match, and are skipped when
+/// none does.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub struct EventFilter {
+    pub sender: Option<Address>,
+    pub app: Option<Address>,
+}
+
+impl EventFilter {
+    fn matches(&self, tx: &TxSummary) -> bool {
+        self.sender.map_or(true, |sender| sender == tx.sender)
+            && self.app.map_or(true, |app| app == tx.app)
+    }
+
+    pub fn apply(&self, mut event: Event) -> Option<Event> {
+        match event.txs_mut() {
+            Some(txs) => {
+                txs.retain(|tx| self.matches(tx));
+                (!txs.is_empty()).then_some(event)
+            }
+            None => match &event {
+                Event::TxAccepted { tx, .. } if self.matches(tx) => Some(event),
+                _ => None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_core::primitives::address;
+
+    const ALICE: Address = address!("0000000000000000000000000000000000000099");
+    const BOB: Address = address!("0000000000000000000000000000000000000045");
+    const APP: Address = address!("0000000000000000000000000000000000000003");
+
+    fn summary(sender: Address, app: Address) -> TxSummary {
+        TxSummary {
+            hash: B256::ZERO,
+            sender,
+            app,
+        }
+    }
+
+    #[test]
+    fn filter_by_sender_and_app() {
+        let accepted = Event::TxAccepted {
+            tx: summary(ALICE, APP),
+            nonce: 0,
+            position: 0,
+        };
+        assert!(EventFilter::default().apply(accepted.clone()).is_some());
+        let alice = EventFilter {
+            sender: Some(ALICE),
+            app: None,
+        };
+        assert!(alice.apply(accepted.clone()).is_some());
+        let bob_on_app = EventFilter {
+            sender: Some(BOB),
+            app: Some(APP),
+        };
+        assert!(bob_on_app.apply(accepted).is_none());
+
+        let sealed = Event::BatchSealed {
+            txs: vec![summary(ALICE, APP), summary(BOB, Address::ZERO)],
+        };
+        assert_eq!(
+            alice.apply(sealed.clone()),
+            Some(Event::BatchSealed {
+                txs: vec![summary(ALICE, APP)]
+            })
+        );
+        assert!(bob_on_app.apply(sealed).is_none());
+    }
+
+    #[test]
+    fn events_are_tagged() {
+        let event = Event::TxAccepted {
+            tx: summary(ALICE, APP),
+            nonce: 3,
+            position: 1,
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["event"], event.kind());
+        assert_eq!(json["nonce"], 3);
+        assert_eq!(json["sender"], serde_json::to_value(ALICE).unwrap());
+    }
+}
@@ -1,77 +1,129 @@
 #![feature(async_closure)]
 use alloy_core::{
-    primitives::{address, Address, Bytes, U256},
+    primitives::{uint, Address, Bytes, B256, U256},
     sol,
-    sol_types::{eip712_domain, Eip712Domain},
+    sol_types::Eip712Domain,
 };
-use alloy_network::EthereumSigner;
+use alloy_network::{EthereumSigner, TxSigner};
 use alloy_node_bindings::Anvil;
 use alloy_node_bindings::AnvilInstance;
 use alloy_provider::{Provider, ProviderBuilder};
 use alloy_rpc_types::TransactionRequest;
-use alloy_signer::k256::ecdsa;
+use alloy_signer::Signature;
 use alloy_signer_wallet::LocalWallet;
-use alloy_signer_wallet::Wallet;
-use anyhow::Error;
+use anyhow::{anyhow, Context, Error};
 use axum::{
-    extract::State,
+    extract::{Query, State},
     http::StatusCode,
+    response::sse::{self, KeepAlive, Sse},
     routing::{get, post},
     Json, Router,
 };
-use message::WireTransaction;
-use message::{AppNonces, BatchBuilder, WalletState};
+use clap::Parser;
+use cli::{Cli, Command};
+use da::{
+    BlobBackend, DaBackend, DaBackendKind, FilesystemBackend, InputBoxBackend, InputServer,
+    L1Sender, RetryPolicy, Submission,
+};
+use events::{EventFilter, TxSummary};
+use futures_util::stream::{self, Stream};
+use message::{Batch, SignedTransaction, Transaction, WireTransaction};
+use message::{BatchBuilder, Checkpoint, WalletState};
+use message::{Preconfirmation, SignedPreconfirmation};
+use message::{SigningMessage, TypedData};
+use oracle::FeeQuote;
+use pending::PendingPool;
 use reqwest;
+use scheduler::{BatchPolicy, BatchScheduler};
 use serde::{Deserialize, Serialize};
+use signer::{RemoteSigner, SequencerSigner};
+use simulate::{Receipt, Rejection, Simulation};
+use status::{TxRecord, TxTracker};
+use std::collections::VecDeque;
+use std::convert::Infallible;
 use std::fs;
+use std::path::Path;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant};
+use submitter::Submitter;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, watch, Mutex, Notify};
 use tokio::task;
 use toml;
-
-pub const DOMAIN: Eip712Domain = eip712_domain!(
+use wal::{Logged, LoggedBatch, Wal};
+
+mod cli;
+mod da;
+mod events;
+mod oracle;
+mod pending;
+mod replay;
+mod scheduler;
+mod signer;
+mod simulate;
+mod snapshot;
+mod status;
+mod submitter;
+mod wal;
+
+// the domain of the default config, which the tests sign with
+#[cfg(test)]
+pub const DOMAIN: Eip712Domain = alloy_core::sol_types::eip712_domain!(
    name: "CartesiPaio",
    version: "0.0.1",
    chain_id: 1337,
    verifying_contract: Address::ZERO,
 );
 
-const USE_LOCAL_ANVIL: bool = false;
-const DEPLOY_INPUT_BOX: bool = true;
+// what the sequencer gets on the dev chain
+const DEV_FUNDS: U256 = uint!(30_000_000_000_000_000_000_U256);
 
 async fn fund_sequencer(
     signer_address: Address,
     sequencer_address: Address,
+    amount: U256,
     provider: Box<dyn Provider<alloy_transport_http::Http<reqwest::Client>>>,
-) {
+) -> Result<(), Error> {
     let tx = TransactionRequest::default()
         .from(signer_address)
         .to(sequencer_address)
-        .value("30000000000000000000".parse().unwrap());
+        .value(amount);
     // Send the transaction and wait for the broadcast.
-    let pending_tx = provider.send_transaction(tx).await.unwrap();
+    let pending_tx = provider.send_transaction(tx).await?;
     // Wait for the transaction to be included and get the receipt.
-    let _receipt = pending_tx.get_receipt().await.unwrap();
-}
-
-// TODO: unify this code. the current problem is that provider does not have a size
-//       known at compile time.
-// async fn deploy_input_box(
-//     signer_address: Address,
-//     sequencer_address: Address,
-//     provider: Box<dyn Provider<alloy_transport_http::Http<reqwest::Client>>>,
-// ) -> Address {
-//     let nonce = provider
-//         .get_transaction_count(signer_address)
-//         .await
-//         .unwrap();
-//     InputBox::deploy_builder(provider)
-//         .nonce(nonce)
-//         .from(signer_address)
-//         .deploy()
-//         .await
-//         .unwrap()
-// }
+    let _receipt = pending_tx.get_receipt().await?;
+    Ok(())
+}
+
+async fn deploy_input_box<P>(provider: P, signer_address: Address) -> Result<Address, Error>
+where
+    P: Provider<alloy_transport_http::Http<reqwest::Client>>,
+{
+    let nonce = provider.get_transaction_count(signer_address).await?;
+    let address = InputBox::deploy_builder(provider)
+        .nonce(nonce)
+        .from(signer_address)
+        .deploy()
+        .await?;
+    Ok(address)
+}
+
+// a provider for `base_url` that signs with `signer`
+fn signing_provider<S>(
+    base_url: &str,
+    signer: S,
+) -> Result<impl Provider<alloy_transport_http::Http<reqwest::Client>> + Clone, Error>
+where
+    S: TxSigner<Signature> + Send + Sync + 'static,
+{
+    let url = base_url
+        .parse()
+        .with_context(|| format!("Invalid base_url {}", base_url))?;
+    Ok(ProviderBuilder::new()
+        .with_recommended_fillers()
+        .signer(EthereumSigner::from(signer))
+        .on_http(url))
+}
 
 sol! {
     function EvmAdvance(
@@ -163,188 +215,1186 @@ sol!(
 #[derive(Deserialize)]
 struct Config {
     base_url: String,
+    // where the API is served
+    #[serde(default = "default_api_listen")]
+    api_listen: String,
     sequencer_address: Address,
-    sequencer_signer_string: String,
+    // the sequencer key comes from exactly one of `sequencer_signer_string`,
+    // `sequencer_keystore` and `remote_signer_url`
+    #[serde(default)]
+    sequencer_signer_string: Option<String>,
+    // an encrypted JSON keystore, whose password is read from
+    // `sequencer_keystore_password_file` or the `TRIPA_KEYSTORE_PASSWORD` variable
+    #[serde(default)]
+    sequencer_keystore: Option<String>,
+    #[serde(default)]
+    sequencer_keystore_password_file: Option<String>,
+    // a signing service holding the key, see `signer::RemoteSigner`
+    #[serde(default)]
+    remote_signer_url: Option<String>,
     input_box_address: Address,
-    // TODO: add domain (see in message/lib)
+    // how far ahead of the expected nonce a transaction can be held
+    #[serde(default = "default_max_nonce_gap")]
+    max_nonce_gap: u64,
+    // for how long a held transaction waits for its gap to be filled
+    #[serde(default = "default_pending_ttl_secs")]
+    pending_ttl_secs: u64,
+    // where the wallet state is saved after each batch and on shutdown
+    #[serde(default)]
+    snapshot_path: Option<String>,
+    // block to start from when rebuilding the state from the InputBox
+    #[serde(default)]
+    input_box_deployment_block: u64,
+    // how many blocks the InputBox logs are queried for at once
+    #[serde(default = "default_replay_block_range")]
+    replay_block_range: u64,
+    // sends the deposits into the payment app through the InputBox
+    #[serde(default)]
+    ether_portal_address: Option<Address>,
+    // where accepted transactions are logged until their batch is posted
+    #[serde(default)]
+    wal_path: Option<String>,
+    // the DA layer batches are posted to
+    #[serde(default)]
+    da_backend: DaBackendKind,
+    // where the `filesystem` backend writes batches
+    #[serde(default = "default_filesystem_da_dir")]
+    filesystem_da_dir: String,
+    // where the `filesystem` backend serves batches as rollup inputs
+    #[serde(default = "default_filesystem_da_listen")]
+    filesystem_da_listen: String,
+    // the open batch is sealed once it reaches this many bytes...
+    #[serde(default = "default_max_batch_bytes")]
+    max_batch_bytes: usize,
+    // ...or this many transactions...
+    #[serde(default = "default_max_batch_txs")]
+    max_batch_txs: usize,
+    // ...or this many seconds after its first transaction
+    #[serde(default = "default_max_batch_age_secs")]
+    max_batch_age_secs: u64,
+    // full batches waiting for the one being sent; past this, new transactions
+    // are turned away until there is room
+    #[serde(default = "default_max_queued_batches")]
+    max_queued_batches: usize,
+    // first wait before retrying a failed RPC call, doubled after each failure
+    #[serde(default = "default_retry_backoff_ms")]
+    retry_backoff_ms: u64,
+    #[serde(default = "default_retry_max_backoff_secs")]
+    retry_max_backoff_secs: u64,
+    // a batch transaction pending for this long is replaced with higher fees...
+    #[serde(default = "default_resubmit_after_secs")]
+    resubmit_after_secs: u64,
+    // ...raised by this percentage
+    #[serde(default = "default_fee_bump_percent")]
+    fee_bump_percent: u64,
+    // a batch is final once this many blocks, counting its own, are on L1
+    #[serde(default = "default_confirmation_depth")]
+    confirmation_depth: u64,
+    // how long shutting down waits for the batches left to be posted
+    #[serde(default = "default_shutdown_timeout_secs")]
+    shutdown_timeout_secs: u64,
+    // how often the DA fees are refreshed from the chain
+    #[serde(default = "default_fee_refresh_secs")]
+    fee_refresh_secs: u64,
+    // charged on top of the DA price, as a percentage
+    #[serde(default = "default_price_markup_percent")]
+    price_markup_percent: u64,
+    // the EIP-712 domain transactions and preconfirmations are signed under,
+    // which must be the one of the payment app
+    #[serde(default = "default_domain_name")]
+    domain_name: String,
+    #[serde(default = "default_domain_version")]
+    domain_version: String,
+    // checked against the chain of `base_url` at startup
+    #[serde(default = "default_chain_id")]
+    chain_id: u64,
+    // the payment app, which verifies the signatures
+    #[serde(default)]
+    payment_app_address: Address,
+}
+
+fn default_api_listen() -> String {
+    "0.0.0.0:3000".to_string()
+}
+
+fn default_max_nonce_gap() -> u64 {
+    16
+}
+
+fn default_pending_ttl_secs() -> u64 {
+    60
+}
+
+fn default_replay_block_range() -> u64 {
+    10_000
+}
+
+fn default_filesystem_da_dir() -> String {
+    "batches".to_string()
+}
+
+fn default_filesystem_da_listen() -> String {
+    "127.0.0.1:5004".to_string()
+}
+
+// leaves room for the blob encoding in a single 128 KiB blob
+fn default_max_batch_bytes() -> usize {
+    120_000
+}
+
+fn default_max_batch_txs() -> usize {
+    1000
+}
+
+fn default_max_batch_age_secs() -> u64 {
+    10
+}
+
+fn default_max_queued_batches() -> usize {
+    4
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    500
+}
+
+fn default_retry_max_backoff_secs() -> u64 {
+    30
+}
+
+fn default_resubmit_after_secs() -> u64 {
+    60
+}
+
+// nodes reject replacements that raise the fees by less than 10%
+fn default_fee_bump_percent() -> u64 {
+    12
+}
+
+fn default_confirmation_depth() -> u64 {
+    12
+}
+
+fn default_shutdown_timeout_secs() -> u64 {
+    120
+}
+
+fn default_fee_refresh_secs() -> u64 {
+    12
+}
+
+fn default_price_markup_percent() -> u64 {
+    10
+}
+
+fn default_domain_name() -> String {
+    "CartesiPaio".to_string()
+}
+
+fn default_domain_version() -> String {
+    "0.0.1".to_string()
+}
+
+fn default_chain_id() -> u64 {
+    1337
 }
 
 impl Config {
-    fn get_signer(&self) -> Wallet<ecdsa::SigningKey> {
-        self.sequencer_signer_string
-            .parse::<alloy_signer_wallet::LocalWallet>()
-            .expect("Could not parse sequencer signature")
+    // the keys holding numbers, which the variables overriding them are read
+    // as; every other key holds a string
+    const INTEGER_KEYS: &'static [&'static str] = &[
+        "max_nonce_gap",
+        "pending_ttl_secs",
+        "input_box_deployment_block",
+        "replay_block_range",
+        "max_batch_bytes",
+        "max_batch_txs",
+        "max_batch_age_secs",
+        "max_queued_batches",
+        "retry_backoff_ms",
+        "retry_max_backoff_secs",
+        "resubmit_after_secs",
+        "fee_bump_percent",
+        "confirmation_depth",
+        "shutdown_timeout_secs",
+        "fee_refresh_secs",
+        "price_markup_percent",
+        "chain_id",
+    ];
+
+    // reads the config file, with the overrides from the environment, and
+    // tells everything that is wrong with it at once
+    fn load(path: &Path) -> Result<Config, Error> {
+        let config_string = fs::read_to_string(path)
+            .with_context(|| format!("Could not read the config file {}", path.display()))?;
+        let mut table: toml::Table = toml::from_str(&config_string)
+            .with_context(|| format!("Could not parse {}", path.display()))?;
+        cli::apply_env_overrides(&mut table, Config::INTEGER_KEYS, std::env::vars());
+        let config: Config = toml::Value::Table(table)
+            .try_into()
+            .with_context(|| format!("Invalid config in {}", path.display()))?;
+        let problems = config.problems();
+        if !problems.is_empty() {
+            return Err(anyhow!(
+                "Invalid config in {}:\n  - {}",
+                path.display(),
+                problems.join("\n  - ")
+            ));
+        }
+        Ok(config)
+    }
+
+    // what would only fail once tripa is running
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Err(e) = self.base_url.parse::<reqwest::Url>() {
+            problems.push(format!("base_url: {}", e));
+        }
+        let key_sources = [
+            self.sequencer_signer_string.is_some(),
+            self.sequencer_keystore.is_some(),
+            self.remote_signer_url.is_some(),
+        ];
+        if key_sources.iter().filter(|set| **set).count() != 1 {
+            problems.push(
+                "sequencer_signer_string, sequencer_keystore, remote_signer_url: exactly one must be set"
+                    .to_string(),
+            );
+        }
+        if let Some(key) = &self.sequencer_signer_string {
+            if key.parse::<LocalWallet>().is_err() {
+                problems.push("sequencer_signer_string: not a private key".to_string());
+            }
+        }
+        if let Some(path) = &self.sequencer_keystore {
+            if !Path::new(path).is_file() {
+                problems.push(format!("sequencer_keystore: no file at {}", path));
+            }
+            if self.sequencer_keystore_password_file.is_none()
+                && !signer::has_keystore_password_var()
+            {
+                problems.push(
+                    "sequencer_keystore: no password, set sequencer_keystore_password_file or TRIPA_KEYSTORE_PASSWORD"
+                        .to_string(),
+                );
+            }
+        }
+        if let Some(url) = &self.remote_signer_url {
+            if let Err(e) = url.parse::<reqwest::Url>() {
+                problems.push(format!("remote_signer_url: {}", e));
+            }
+        }
+        if self.api_listen.parse::<std::net::SocketAddr>().is_err() {
+            problems.push(format!(
+                "api_listen: {} is not an address to listen on",
+                self.api_listen
+            ));
+        }
+        if self.da_backend == DaBackendKind::Filesystem
+            && self
+                .filesystem_da_listen
+                .parse::<std::net::SocketAddr>()
+                .is_err()
+        {
+            problems.push(format!(
+                "filesystem_da_listen: {} is not an address to listen on",
+                self.filesystem_da_listen
+            ));
+        }
+        for (key, value) in [
+            ("max_batch_bytes", self.max_batch_bytes as u64),
+            ("max_batch_txs", self.max_batch_txs as u64),
+            ("max_queued_batches", self.max_queued_batches as u64),
+            ("fee_refresh_secs", self.fee_refresh_secs),
+            ("replay_block_range", self.replay_block_range),
+        ] {
+            if value == 0 {
+                problems.push(format!("{}: must be at least 1", key));
+            }
+        }
+        problems
+    }
+
+    // where the state is rebuilt from when there is no snapshot
+    fn replay_source(&self) -> replay::ReplaySource {
+        replay::ReplaySource {
+            input_box_address: self.input_box_address,
+            app: self.input_box_address,
+            sequencer: self.sequencer_address,
+            ether_portal_address: self.ether_portal_address,
+            from_block: self.input_box_deployment_block,
+            confirmation_depth: self.confirmation_depth,
+            block_range: self.replay_block_range,
+            blobs: self.da_backend == DaBackendKind::Blob,
+        }
+    }
+
+    fn domain(&self) -> Eip712Domain {
+        Eip712Domain::new(
+            Some(self.domain_name.clone().into()),
+            Some(self.domain_version.clone().into()),
+            Some(U256::from(self.chain_id)),
+            Some(self.payment_app_address),
+            None,
+        )
+    }
+
+    // the local key, from the config or the keystore, if the key is not remote
+    fn local_signer(&self) -> Result<Option<LocalWallet>, Error> {
+        match (&self.sequencer_signer_string, &self.sequencer_keystore) {
+            (Some(key), _) => Ok(Some(
+                key.parse()
+                    .context("Could not parse sequencer_signer_string")?,
+            )),
+            (None, Some(path)) => Ok(Some(signer::open_keystore(
+                Path::new(path),
+                self.sequencer_keystore_password_file
+                    .as_deref()
+                    .map(Path::new),
+            )?)),
+            (None, None) => Ok(None),
+        }
+    }
+
+    async fn sequencer_signer(&self) -> Result<SequencerSigner, Error> {
+        if let Some(wallet) = self.local_signer()? {
+            return Ok(SequencerSigner::Local(wallet));
+        }
+        let url = self
+            .remote_signer_url
+            .as_ref()
+            .ok_or(anyhow!("No sequencer key is set"))?;
+        let remote = RemoteSigner::connect(url)
+            .await
+            .with_context(|| format!("Could not reach the remote signer at {}", url))?;
+        println!("Signing with the remote signer at {}", url);
+        Ok(SequencerSigner::Remote(remote))
+    }
+
+    fn da_backend(
+        &self,
+        signer: &SequencerSigner,
+        provider: Box<dyn Provider<alloy_transport_http::Http<reqwest::Client>>>,
+    ) -> Result<Arc<dyn DaBackend>, Error> {
+        Ok(match self.da_backend {
+            DaBackendKind::InputBox => Arc::new(InputBoxBackend::new(
+                self.l1_sender(signer.clone())?,
+                self.input_box_address,
+                provider,
+                self.max_batch_bytes,
+            )),
+            DaBackendKind::Blob => Arc::new(BlobBackend::new(
+                self.l1_sender(signer.clone())?,
+                self.input_box_address,
+                provider,
+                self.max_batch_bytes,
+            )),
+            DaBackendKind::Filesystem => Arc::new(
+                FilesystemBackend::new(Path::new(&self.filesystem_da_dir)).with_context(|| {
+                    format!("Could not open the DA directory {}", self.filesystem_da_dir)
+                })?,
+            ),
+        })
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            initial_backoff: Duration::from_millis(self.retry_backoff_ms),
+            max_backoff: Duration::from_secs(self.retry_max_backoff_secs),
+            resubmit_after: Duration::from_secs(self.resubmit_after_secs),
+            fee_bump_percent: self.fee_bump_percent,
+        }
+    }
+
+    fn l1_sender(&self, signer: SequencerSigner) -> Result<L1Sender, Error> {
+        L1Sender::new(&self.base_url, signer, self.retry_policy())
+            .context("Could not create the L1 sender")
+    }
+
+    fn input_server(&self) -> InputServer {
+        InputServer {
+            dir: self.filesystem_da_dir.clone().into(),
+            chain_id: self.chain_id,
+            app_contract: self.input_box_address,
+            msg_sender: self.sequencer_address,
+            next_input: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    async fn fee_oracle(&self, da: Arc<dyn DaBackend>) -> watch::Receiver<Option<FeeQuote>> {
+        oracle::spawn(
+            da,
+            Duration::from_secs(self.fee_refresh_secs),
+            self.price_markup_percent,
+        )
+        .await
+    }
+
+    fn batch_scheduler(&self) -> BatchScheduler {
+        BatchScheduler::new(BatchPolicy {
+            max_bytes: self.max_batch_bytes,
+            max_txs: self.max_batch_txs,
+            max_age: Duration::from_secs(self.max_batch_age_secs),
+        })
+    }
+
+    fn pending_pool(&self) -> PendingPool {
+        PendingPool::new(
+            self.max_nonce_gap,
+            Duration::from_secs(self.pending_ttl_secs),
+        )
     }
 }
 
+// a sealed batch that is not final yet
+struct SealedBatch {
+    // where the batch starts in `wallet_state`
+    checkpoint: Checkpoint,
+    batch: Batch,
+    // where it was included, once it was
+    submission: Option<Submission>,
+}
+
 struct Lambda {
     wallet_state: WalletState,
+    // what transactions and preconfirmations are signed under
+    domain: Eip712Domain,
+    // how many batches of the sequencer are final; the next ones are numbered
+    // from here, in the order they are sealed
+    final_batches: u64,
+    // signs the preconfirmations
+    signer: SequencerSigner,
+    // the sealed batches that are not final yet, oldest first; only the
+    // changes before the first one are final
+    sealed: VecDeque<SealedBatch>,
+    // how many of the newest sealed batches wait for the one being sent
+    queued: usize,
+    // whether a batch was handed to the submitter and is not included yet
+    sending: bool,
+    // where the open batch starts in `wallet_state`
+    open: Checkpoint,
     batch_builder: BatchBuilder,
+    // tells when `batch_builder` should be sealed
+    scheduler: BatchScheduler,
+    // wakes up the batch task when the open batch changes
+    seal: Arc<Notify>,
+    // transactions waiting for a nonce gap to be filled
+    pending: PendingPool,
+    // what became of the accepted transactions
+    tracker: TxTracker,
+    // log of the accepted transactions that are not final yet
+    wal: Option<Wal>,
     config: Config,
-    provider: Box<dyn Provider<alloy_transport_http::Http<reqwest::Client>>>,
+    da: Arc<dyn DaBackend>,
+    // latest DA price, kept up to date in the background
+    fees: watch::Receiver<Option<FeeQuote>>,
     // used to keep anvil alive during the lifetime of Lambda
     _anvil_instance: Option<AnvilInstance>,
 }
 
 impl Lambda {
-    // TODO: send the build_batch logic to the specific DA backend
-    async fn build_batch(&mut self) -> Result<(), Error> {
-        let signer = self.config.get_signer();
+    fn expected_nonce(&self, app: &Address, user: &Address) -> u64 {
+        self.wallet_state
+            .app_nonces
+            .get(app)
+            .and_then(|app_nonces| app_nonces.get_nonce(user))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    // verifies `tx` and adds it to the open batch, returning `None` if it is not valid.
+    // the transaction is only accepted once it is in the WAL, otherwise the state is
+    // left untouched
+    fn admit(&mut self, tx: SignedTransaction) -> Result<Option<Transaction>, Error> {
+        let wire_tx = WireTransaction::from_signed_transaction(&tx);
+        // the open batch never goes over its limits, the transaction goes
+        // to the next one instead
+        if !self.scheduler.fits(wire_tx.encoded_len()) {
+            self.seal_batch();
+        }
+        let checkpoint = self.wallet_state.checkpoint();
+        let sequencer_address = self.config.sequencer_address;
+        let balance = |wallet_state: &WalletState| {
+            wallet_state
+                .balances
+                .get(&sequencer_address)
+                .copied()
+                .unwrap_or_default()
+        };
+        let balance_before = balance(&self.wallet_state);
+        let Some(transaction) = self.wallet_state.verify_single(sequencer_address, &wire_tx) else {
+            self.wallet_state.commit(checkpoint);
+            return Ok(None);
+        };
+        if let Some(wal) = &mut self.wal {
+            if let Err(e) = wal.append_transaction(&tx) {
+                self.wallet_state.rollback(checkpoint);
+                return Err(e);
+            }
+        }
+        self.wallet_state.commit(checkpoint);
+        // what the sequencer was paid, nothing if it sent the transaction itself
+        let fee = balance(&self.wallet_state) - balance_before;
+        self.tracker.admitted(wire_tx.hash(), &transaction, fee);
+        self.batch_builder.add(tx);
+        let opened = self.scheduler.is_empty();
+        if self.scheduler.add(wire_tx.encoded_len()) || opened {
+            // either the batch is full, or there is a new deadline to wait for
+            self.seal.notify_one();
+        }
+        Ok(Some(transaction))
+    }
 
-        // get the current batch and reset the batch builder
-        let batch = self.batch_builder.clone().build();
-        self.batch_builder = BatchBuilder::new(self.config.sequencer_address);
+    // moves held transactions into the batch for as long as their nonces follow
+    fn promote_pending(&mut self, app: Address, sender: Address) {
+        loop {
+            let nonce = self.expected_nonce(&app, &sender);
+            let Some(tx) = self.pending.take(app, sender, nonce) else {
+                break;
+            };
+            if !self.has_room(WireTransaction::from_signed_transaction(&tx).encoded_len()) {
+                self.pending.hold(sender, tx);
+                break;
+            }
+            match self.admit(tx.clone()) {
+                Ok(Some(_)) => {}
+                Ok(None) => {
+                    let hash = WireTransaction::from_signed_transaction(&tx).hash();
+                    self.tracker.dropped(&[hash], "not valid");
+                    break;
+                }
+                Err(e) => {
+                    println!("Could not log transaction: {}", e);
+                    self.pending.hold(sender, tx);
+                    break;
+                }
+            }
+        }
+    }
 
-        let provider = ProviderBuilder::new()
-            .with_recommended_fillers()
-            .signer(EthereumSigner::from(signer.clone()))
-            .on_http(self.config.base_url.parse().unwrap());
-        // TODO: try to use the lambda's provider instead, but it seems that
-        //       it cannot be cloned. or something
-        //         let provider = self.provider.clone();
-
-        let input_contract = InputBox::new(self.config.input_box_address, provider);
-
-        // TODO: calculate gas needed
-        // TODO: calculate gas price
-        let tx = input_contract.addInput(
-            self.config.input_box_address,
-            Bytes::copy_from_slice(&batch.clone().to_bytes()),
+    // holds `tx` until the gap before its nonce is filled, in place of a held
+    // transaction with the same nonce
+    fn hold(&mut self, sender: Address, tx: SignedTransaction) {
+        let hash = WireTransaction::from_signed_transaction(&tx).hash();
+        let summary = TxSummary {
+            hash,
+            sender,
+            app: tx.message.app,
+        };
+        if let Some(replaced) = self.pending.hold(sender, tx) {
+            let replaced = WireTransaction::from_signed_transaction(&replaced).hash();
+            if replaced != hash {
+                self.tracker.dropped(&[replaced], "replaced");
+            }
+        }
+        self.tracker.held(summary);
+    }
+
+    // drops the held transactions that waited too long for their gap to be filled
+    fn prune_pending(&mut self) {
+        let expired: Vec<B256> = self
+            .pending
+            .prune()
+            .iter()
+            .map(|tx| WireTransaction::from_signed_transaction(tx).hash())
+            .collect();
+        if !expired.is_empty() {
+            println!("Dropped {} expired pending transactions", expired.len());
+            self.tracker.dropped(&expired, "expired");
+        }
+    }
+
+    // whether a transaction of `len` bytes can be admitted, either into the
+    // open batch or into the next one, if there is room in the queue to seal
+    // the open batch first
+    fn has_room(&self, len: usize) -> bool {
+        self.scheduler.fits(len) || self.queued < self.config.max_queued_batches
+    }
+
+    // when the batch task should wake up to seal the open batch; there is
+    // no deadline while another batch is being sent
+    fn deadline(&self) -> Option<Instant> {
+        match self.sending {
+            true => None,
+            false => self.scheduler.deadline(),
+        }
+    }
+
+    // seals the open batch if there is room in the queue and it is full, or
+    // too old while no batch is being sent, so that it keeps growing meanwhile
+    fn seal_due(&mut self, now: Instant) {
+        let due = match self.sending {
+            true => self.scheduler.is_full(),
+            false => self.scheduler.is_due(now),
+        };
+        if due && self.queued < self.config.max_queued_batches {
+            self.seal_batch();
+        }
+    }
+
+    // moves the open batch to the queue of sealed batches, unless it is empty
+    fn seal_batch(&mut self) {
+        if self.batch_builder.txs.is_empty() {
+            return;
+        }
+        let builder = std::mem::replace(
+            &mut self.batch_builder,
+            BatchBuilder::new(self.config.sequencer_address),
         );
+        let batch = builder.build();
+        println!("Sealed batch with {} transactions", batch.txs.len());
+        self.scheduler.reset();
+        self.log_to_wal(Wal::seal);
+        self.tracker.sealed();
+        self.sealed.push_back(SealedBatch {
+            checkpoint: self.open,
+            batch,
+            submission: None,
+        });
+        self.open = self.wallet_state.checkpoint();
+        self.queued += 1;
+    }
 
-        // build event for watching
-        let event = input_contract.InputAdded_filter();
-        let _ = tx.send().await?.get_receipt().await?;
-        // now go listen to the events
-        let log = event.query().await.unwrap();
-        let event = &log[0].0;
-
-        // testing if the batch is contained in the logs
-        // TODO: improve this test to see if it is correctly inserted
-        let b: &[u8] = &batch.clone().to_bytes();
-        let r = event
-            .input
-            .clone()
-            .windows(b.len())
-            .position(|window| window == b);
-        assert!(r.is_some());
+    // takes the oldest queued batch to be submitted, unless the previous one
+    // is not included yet. batches are sent one at a time, so that they land
+    // on L1 in the order their transactions were verified
+    fn next_batch(&mut self) -> Option<Batch> {
+        if self.sending || self.queued == 0 {
+            return None;
+        }
+        let index = self.sealed.len() - self.queued;
+        self.queued -= 1;
+        self.sending = true;
+        // a full open batch may have been waiting for room in the queue
+        self.seal_due(Instant::now());
+        Some(self.sealed[index].batch.clone())
+    }
 
-        // TODO: the improvement below does not work for some reason
-        // let input = event.input.clone();
-        // let decoded_advance =
-        //     EvmAdvanceCall::abi_decode_raw(&input, true).unwrap();
-        // let emitted_batch = decoded_advance.payload;
-        // assert_eq!(emitted_batch, batch.to_bytes());
+    // the batch taken by `next_batch` did not reach the submitter, it is
+    // queued again
+    fn batch_not_taken(&mut self) {
+        self.sending = false;
+        self.queued += 1;
+    }
+
+    // the batch being sent made it to a block, the next one can be sent
+    fn batch_included(&mut self, submission: &Submission) {
+        let index = self.sealed.len() - self.queued - 1;
+        self.tracker.submitted(index, submission);
+        self.sealed[index].submission = Some(submission.clone());
+        self.rewrite_wal();
+        self.sending = false;
+        // the next batch may be queued, or the open batch already due
+        self.seal.notify_one();
+    }
 
-        // TODO: in production someone can break the above assertions
-        //       by submitting an input at the same time
+    // the oldest sealed batch is deep enough to be safe from reorgs,
+    // its changes are kept for good
+    fn batch_finalized(&mut self) {
+        let Some(sealed) = self.sealed.pop_front() else {
+            return;
+        };
+        self.wallet_state.release(sealed.checkpoint);
+        self.final_batches += 1;
+        self.tracker.finalized();
+        println!("State root {}", self.confirmed_state().state_root());
+        self.write_snapshot();
+        self.rewrite_wal();
+    }
 
-        println!("log {:?}", log);
+    // the sealed batch at `index` was posted again, after a reorg removed it
+    fn batch_resubmitted(&mut self, index: usize, submission: &Submission) {
+        self.tracker.submitted(index, submission);
+        if let Some(sealed) = self.sealed.get_mut(index) {
+            sealed.submission = Some(submission.clone());
+        }
+        self.rewrite_wal();
+    }
 
-        // TODO: do more error handling
-        Ok(())
+    // the sealed batch at `index` could not be posted. the state only reflects
+    // batches that made it to L1, so it is undone together with everything
+    // accepted after it. the transactions are then verified again, and the ones
+    // still valid are queued in the batches they were in, which keeps their
+    // preconfirmations
+    fn batch_failed(&mut self, index: usize, reason: &str) {
+        let Some(checkpoint) = self.sealed.get(index).map(|sealed| sealed.checkpoint) else {
+            return;
+        };
+        self.wallet_state.rollback(checkpoint);
+        let batches: Vec<Vec<SignedTransaction>> = self
+            .sealed
+            .drain(index..)
+            .map(|sealed| {
+                sealed
+                    .batch
+                    .txs
+                    .iter()
+                    .map(WireTransaction::to_signed_transaction)
+                    .collect()
+            })
+            .collect();
+        let open = std::mem::replace(
+            &mut self.batch_builder,
+            BatchBuilder::new(self.config.sequencer_address),
+        )
+        .txs;
+        self.scheduler.reset();
+        self.tracker.undone(index);
+        self.open = self.wallet_state.checkpoint();
+        self.queued = 0;
+        self.sending = false;
+
+        let invalid = self.readmit(batches, open);
+        let hashes: Vec<B256> = invalid
+            .iter()
+            .map(|tx| WireTransaction::from_signed_transaction(tx).hash())
+            .collect();
+        self.tracker.dropped(&hashes, reason);
+        self.rewrite_wal();
+        self.seal.notify_one();
     }
-}
 
-type LambdaMutex = Mutex<Lambda>;
+    // verifies again transactions accepted before, sealing them in the batches
+    // they were in, followed by the open transactions; returns the ones that
+    // are no longer valid. they are already in the log, which is left alone
+    fn readmit(
+        &mut self,
+        batches: Vec<Vec<SignedTransaction>>,
+        open: Vec<SignedTransaction>,
+    ) -> Vec<SignedTransaction> {
+        let wal = self.wal.take();
+        let mut invalid = Vec::new();
+        let sealed = batches.len();
+        for (i, txs) in batches.into_iter().chain([open]).enumerate() {
+            for tx in txs {
+                if !matches!(self.admit(tx.clone()), Ok(Some(_))) {
+                    invalid.push(tx);
+                }
+            }
+            if i < sealed {
+                self.seal_batch();
+            }
+        }
+        self.wal = wal;
+        invalid
+    }
+
+    // writes the log from scratch, once the sealed batches changed
+    fn rewrite_wal(&mut self) {
+        if self.wal.is_none() {
+            return;
+        }
+        let batches = self
+            .sealed
+            .iter()
+            .map(|sealed| LoggedBatch {
+                txs: sealed
+                    .batch
+                    .txs
+                    .iter()
+                    .map(WireTransaction::to_signed_transaction)
+                    .collect(),
+                submission: sealed.submission.clone(),
+            })
+            .collect();
+        let logged = Logged {
+            batches,
+            open: self.batch_builder.txs.clone(),
+        };
+        self.log_to_wal(|wal| wal.rewrite(&logged));
+    }
+
+    // a promise that the transaction keeps its place in its batch, which is
+    // numbered after the final batches
+    fn preconfirmation(&self, hash: B256) -> Result<Preconfirmation, Error> {
+        let not_tracked = || anyhow!("transaction {} is not tracked", hash);
+        let record = self.tracker.get(&hash).ok_or_else(not_tracked)?;
+        let batch = self.tracker.batch_index(&hash).ok_or_else(not_tracked)?;
+        let preconfirmation = Preconfirmation {
+            tx_hash: hash,
+            batch: self.final_batches + batch as u64,
+            position: record.position as u64,
+        };
+        Ok(preconfirmation)
+    }
+
+    // what `submit_transaction` would do with `tx` if the DA price were
+    // `price_per_byte`, without changing anything
+    fn simulate(&self, tx: &WireTransaction, price_per_byte: u128) -> Simulation {
+        let hash = tx.hash();
+        let sender = match tx.to_signed_transaction().recover(&self.domain) {
+            Err(_) => return Simulation::Rejected(Rejection::InvalidSignature),
+            Ok(sender) => sender,
+        };
+        if tx.max_gas_price < price_per_byte {
+            return Simulation::Rejected(Rejection::GasPriceTooLow {
+                offered: tx.max_gas_price,
+                needed: price_per_byte,
+            });
+        }
+        let len = tx.encoded_len();
+        if !self.scheduler.fits_empty(len) {
+            return Simulation::Rejected(Rejection::TooLarge {
+                size: len,
+                max: self.config.max_batch_bytes,
+            });
+        }
+        let expected_nonce = self.expected_nonce(&tx.app, &sender);
+        if self.pending.accepts(expected_nonce, tx.nonce) {
+            return Simulation::Held {
+                hash,
+                expected_nonce,
+            };
+        }
+        if !self.has_room(len) {
+            return Simulation::Rejected(Rejection::Congested);
+        }
+        // a transaction that does not fit starts the next batch
+        let (batch, position) = match self.scheduler.fits(len) {
+            true => (self.sealed.len(), self.batch_builder.txs.len()),
+            false => (self.sealed.len() + 1, 0),
+        };
+        match simulate::dry_run(&self.wallet_state, self.config.sequencer_address, tx) {
+            Err(e) => Simulation::Rejected(e.into()),
+            Ok(run) => Simulation::Accepted(Receipt {
+                hash,
+                sender,
+                app: tx.app,
+                nonce: tx.nonce,
+                batch: self.final_batches + batch as u64,
+                position,
+                cost: run.transaction.cost().unwrap_or(U256::MAX),
+                fee: run.fee,
+                underfunded: run.underfunded,
+                balance: run.balance,
+            }),
+        }
+    }
+
+    // the state as of the last final batch
+    fn confirmed_state(&self) -> WalletState {
+        let mut wallet_state = self.wallet_state.clone();
+        let checkpoint = match self.sealed.front() {
+            Some(sealed) => sealed.checkpoint,
+            None => self.open,
+        };
+        wallet_state.rollback(checkpoint);
+        wallet_state
+    }
+
+    fn log_to_wal(&mut self, f: impl FnOnce(&mut Wal) -> Result<(), Error>) {
+        if let Some(wal) = &mut self.wal {
+            if let Err(e) = f(wal) {
+                println!("Could not write to the WAL: {}", e);
+            }
+        }
+    }
+
+    // re-admits the transactions that were accepted but are not final, in the
+    // batches they were sealed in, so that their preconfirmations hold. the
+    // batches included on L1 are only followed until they are final, the
+    // others are queued to be posted
+    fn restore_from_wal(&mut self, logged: Logged) {
+        let mut invalid = 0;
+        for batch in logged.batches {
+            let sealed = self.sealed.len();
+            invalid += self.readmit(vec![batch.txs], Vec::new()).len();
+            // a batch left empty is not sealed again
+            if let (true, Some(submission)) = (self.sealed.len() > sealed, batch.submission) {
+                self.tracker.submitted(sealed, &submission);
+                self.sealed[sealed].submission = Some(submission);
+                self.queued -= 1;
+            }
+        }
+        invalid += self.readmit(Vec::new(), logged.open).len();
+        println!(
+            "Restored {} sealed batches and {} open transactions, dropped {} that are no longer valid",
+            self.sealed.len(),
+            self.batch_builder.txs.len(),
+            invalid
+        );
+        // the batch task may have queued batches to send
+        self.seal.notify_one();
+    }
+
+    // whether tripa can leave without losing a batch: every batch is included,
+    // as the WAL follows them after a restart until they are final, or final
+    // when there is no WAL
+    fn settled(&self) -> bool {
+        self.batch_builder.txs.is_empty()
+            && match self.wal {
+                Some(_) => self.sealed.iter().all(|sealed| sealed.submission.is_some()),
+                None => self.sealed.is_empty(),
+            }
+    }
 
-fn mock_state() -> WalletState {
-    let john_address = address!("0000000000000000000000000000000000000099");
-    let joe_address = address!("0000000000000000000000000000000000000045");
-    let app1_address = address!("0000000000000000000000000000000000000003");
-    let app2_address = address!("0000000000000000000000000000000000000023");
-    let signer_address = address!("7306897365c277A6951FDA9519fD0CCc16341E4A");
-    let mut app1_nonces: AppNonces = AppNonces::default();
-    app1_nonces.set_nonce(john_address, 3);
-    app1_nonces.set_nonce(joe_address, 15);
-    let mut app2_nonces: AppNonces = AppNonces::default();
-    app2_nonces.set_nonce(john_address, 22);
-    let mut wallet_state: WalletState = WalletState::new(DOMAIN);
-    wallet_state.add_app_nonce(app1_address, app1_nonces);
-    wallet_state.add_app_nonce(app2_address, app2_nonces);
-    wallet_state.deposit(john_address, U256::from(2000000000));
-    wallet_state.deposit(joe_address, U256::from(321));
-    wallet_state.deposit(signer_address, U256::from(2000000000));
-    wallet_state
+    // the snapshot only reflects the batches that are final
+    fn write_snapshot(&self) {
+        let Some(path) = &self.config.snapshot_path else {
+            return;
+        };
+        let wallet_state = self.confirmed_state();
+        if let Err(e) = snapshot::write(Path::new(path), &wallet_state, self.final_batches) {
+            println!("Could not write snapshot to {:?}: {}", path, e);
+        }
+    }
 }
 
+type LambdaMutex = Mutex<Lambda>;
+
 #[tokio::main]
 async fn main() {
-    let config_string = fs::read_to_string("config.toml").unwrap();
-    let mut config: Config = toml::from_str(&config_string).unwrap();
-
-    // Create a provider with the HTTP transport using the `reqwest` crate.
-    let (provider, signer) = if USE_LOCAL_ANVIL {
-        let anvil = Anvil::new().try_spawn().expect("Anvil not working");
-        let signer: LocalWallet = anvil.keys()[0].clone().into();
-        let rpc_url: String = anvil.endpoint().parse().expect("Could not get Anvil's url");
-        config.base_url = rpc_url.clone();
-        (
-            Box::new(
-                ProviderBuilder::new()
-                    .with_recommended_fillers()
-                    .signer(EthereumSigner::from(signer.clone()))
-                    .on_http(config.base_url.parse().unwrap()),
-            ),
-            signer,
-        )
-    } else {
-        let signer = config
-            .sequencer_signer_string
-            .parse::<alloy_signer_wallet::LocalWallet>()
-            .expect("Could not parse sequencer signature");
-        (
-            Box::new(
-                ProviderBuilder::new()
-                    .with_recommended_fillers()
-                    .signer(EthereumSigner::from(signer.clone()))
-                    .on_http(config.base_url.parse().unwrap()),
-            ),
-            signer,
-        )
+    let cli = Cli::parse();
+    let result = match Config::load(&cli.config) {
+        Err(e) => Err(e),
+        Ok(config) => match cli.command.unwrap_or(Command::Serve { dev: false }) {
+            Command::Serve { dev } => serve(config, dev).await,
+            Command::DeployInputbox => deploy(config).await,
+            Command::Fund { key, amount } => fund(config, &key, amount).await,
+            Command::Signer { listen } => serve_signer(config, &listen).await,
+        },
     };
+    if let Err(e) = result {
+        eprintln!("{:#}", e);
+        std::process::exit(1);
+    }
+}
 
+// deploys an InputBox paid by the sequencer key, to be set as `input_box_address`
+async fn deploy(config: Config) -> Result<(), Error> {
+    let signer = config.sequencer_signer().await?;
+    let provider = signing_provider(&config.base_url, signer.clone())?;
+    let address = deploy_input_box(provider, signer.address()).await?;
+    println!("InputBox deployed at {}", address);
+    Ok(())
+}
+
+// sends `amount` wei from the account of `key` to the sequencer, which pays
+// for the batch transactions
+async fn fund(config: Config, key: &str, amount: U256) -> Result<(), Error> {
+    let funder: LocalWallet = key.parse().context("Could not parse the funding key")?;
+    let provider = signing_provider(&config.base_url, funder.clone())?;
+    let sequencer = config.sequencer_signer().await?.address();
+    fund_sequencer(funder.address(), sequencer, amount, Box::new(provider)).await?;
+    println!("Sent {} wei to {}", amount, sequencer);
+    Ok(())
+}
+
+// serves the local sequencer key the way a signing service would, so that
+// another tripa can run with `remote_signer_url`
+async fn serve_signer(config: Config, listen: &str) -> Result<(), Error> {
+    let wallet = config
+        .local_signer()?
+        .ok_or(anyhow!("The signer needs a local key, not a remote one"))?;
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("Could not listen on {}", listen))?;
+    println!("Signing as {} on {}", wallet.address(), listen);
+    axum::serve(listener, signer::stand_in(wallet))
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+    Ok(())
+}
+
+// spawns a local anvil on the chain of the domain, where the sequencer is
+// funded and gets an InputBox of its own
+async fn start_dev_chain(config: &mut Config, sequencer: Address) -> Result<AnvilInstance, Error> {
+    let anvil = Anvil::new()
+        .chain_id(config.chain_id)
+        .try_spawn()
+        .context("Could not spawn Anvil")?;
+    config.base_url = anvil.endpoint();
+    let funder: LocalWallet = anvil.keys()[0].clone().into();
+    let provider = signing_provider(&config.base_url, funder.clone())?;
     fund_sequencer(
-        signer.address(),
-        config.sequencer_address,
+        funder.address(),
+        sequencer,
+        DEV_FUNDS,
         Box::new(provider.clone()),
     )
-    .await;
+    .await?;
+    config.input_box_address = deploy_input_box(provider, funder.address()).await?;
+    config.input_box_deployment_block = 0;
+    println!(
+        "Dev chain at {}, InputBox at {}",
+        config.base_url, config.input_box_address
+    );
+    Ok(anvil)
+}
 
-    if DEPLOY_INPUT_BOX {
-        let nonce = provider
-            .get_transaction_count(signer.address())
-            .await
-            .unwrap();
-        config.input_box_address = InputBox::deploy_builder(provider.clone())
-            .nonce(nonce)
-            .from(signer.address())
-            .deploy()
+// runs the sequencer until ctrl-c, then waits for every batch to be final
+async fn serve(mut config: Config, dev: bool) -> Result<(), Error> {
+    let signer = config.sequencer_signer().await?;
+    // batches are counted by their sender, and the sequencer pays itself the
+    // fees, so the key must be the one of the configured address
+    if signer.address() != config.sequencer_address {
+        return Err(anyhow!(
+            "The sequencer key is for {}, but sequencer_address is {}",
+            signer.address(),
+            config.sequencer_address
+        ));
+    }
+    // the filesystem backend runs without a node
+    let offline = config.da_backend == DaBackendKind::Filesystem;
+    // kept alive for as long as tripa runs
+    let anvil = match dev && !offline {
+        true => Some(start_dev_chain(&mut config, signer.address()).await?),
+        false => None,
+    };
+    let provider = signing_provider(&config.base_url, signer.clone())?;
+
+    // signatures made for another chain must not be accepted here
+    if !offline {
+        let chain_id = provider
+            .get_chain_id()
             .await
-            .unwrap()
+            .context("Could not get the chain id")?;
+        if chain_id != config.chain_id {
+            return Err(anyhow!(
+                "The node at {} is on chain {}, but the domain is for chain {}",
+                config.base_url,
+                chain_id,
+                config.chain_id
+            ));
+        }
     }
 
-    let wallet_state = mock_state();
-    let lambda: LambdaMutex = Mutex::new(Lambda {
+    let loaded_state = match &config.snapshot_path {
+        Some(path) => snapshot::load(Path::new(path))
+            .with_context(|| format!("Could not load the snapshot {}", path))?,
+        None => None,
+    };
+    let (mut wallet_state, final_batches) = match loaded_state {
+        Some((wallet_state, batches)) => {
+            if wallet_state.domain != config.domain() {
+                return Err(anyhow!(
+                    "The snapshot domain does not match the sequencer domain"
+                ));
+            }
+            println!("Loaded snapshot, state root {}", wallet_state.state_root());
+            (wallet_state, batches)
+        }
+        None if offline => {
+            println!("No snapshot found, replaying the written batches");
+            da::replay_filesystem(Path::new(&config.filesystem_da_dir), config.domain())
+                .await
+                .context("Could not rebuild the state from the written batches")?
+        }
+        None => {
+            println!("No snapshot found, replaying the InputBox history");
+            replay::replay(
+                ProviderBuilder::new().on_http(config.base_url.parse()?),
+                &config.replay_source(),
+                config.domain(),
+            )
+            .await
+            .context("Could not rebuild the state from the InputBox")?
+        }
+    };
+    let open = wallet_state.checkpoint();
+    let da = config.da_backend(&signer, Box::new(provider))?;
+    let fees = config.fee_oracle(da.clone()).await;
+    let wal = config
+        .wal_path
+        .as_ref()
+        .map(|path| {
+            Wal::open(Path::new(path)).with_context(|| format!("Could not open the WAL {}", path))
+        })
+        .transpose()?;
+    let mut lambda = Lambda {
         wallet_state,
+        domain: config.domain(),
+        final_batches,
+        signer,
+        sealed: VecDeque::new(),
+        queued: 0,
+        sending: false,
+        open,
         batch_builder: BatchBuilder::new(config.sequencer_address),
+        scheduler: config.batch_scheduler(),
+        seal: Arc::new(Notify::new()),
+        pending: config.pending_pool(),
+        tracker: TxTracker::default(),
+        wal: None,
+        da,
+        fees,
         config,
-        provider,
-        _anvil_instance: None,
-    });
+        _anvil_instance: anvil,
+    };
+    if let Some((wal, logged)) = wal {
+        lambda.restore_from_wal(logged);
+        // transactions no longer valid are left out of the log
+        lambda.wal = Some(wal);
+        lambda.rewrite_wal();
+    }
+    let lambda: LambdaMutex = Mutex::new(lambda);
+
+    if lambda.lock().await.config.da_backend == DaBackendKind::Filesystem {
+        let (listen, input_server) = {
+            let config = &lambda.lock().await.config;
+            (config.filesystem_da_listen.clone(), config.input_server())
+        };
+        let listener = tokio::net::TcpListener::bind(&listen)
+            .await
+            .with_context(|| format!("Could not listen on {}", listen))?;
+        println!("Serving batches as rollup inputs on {}", listen);
+        // serves the batches to the app, like the rollup http server would
+        task::spawn(async move {
+            if let Err(e) = axum::serve(listener, input_server.router()).await {
+                println!("Stopped serving batches: {}", e);
+            }
+        });
+    }
 
     let shared_state = Arc::new(lambda);
 
     let state_copy_for_batches = shared_state.clone();
+    let state_copy_for_shutdown = shared_state.clone();
+
+    // sealed batches are handed to the submitter task, which posts them without
+    // holding the state, so transactions keep being admitted in the meantime
+    let (batches, sealed_batches) = mpsc::channel::<Batch>(1);
+    let submitter = {
+        let state = shared_state.lock().await;
+        let mut submitter = Submitter::new(
+            state.da.clone(),
+            state.config.retry_policy(),
+            state.config.confirmation_depth,
+            shared_state.clone(),
+            sealed_batches,
+        );
+        // restored batches that were already included
+        for sealed in &state.sealed {
+            if let Some(submission) = &sealed.submission {
+                submitter.follow(sealed.batch.clone(), submission.clone());
+            }
+        }
+        submitter
+    };
+    task::spawn(submitter.run());
 
-    // this thread will periodically try to build a batch
+    // this task seals the open batch once it is full or too old
+    let seal = shared_state.lock().await.seal.clone();
+    let batches_for_shutdown = batches.clone();
     task::spawn(async move {
         loop {
-            println!("Building batch...");
-            // TODO: investigate why there are no transactions when the batch is empty
+            let deadline = state_copy_for_batches.lock().await.deadline();
+            match deadline {
+                Some(deadline) => {
+                    tokio::select! {
+                        _ = tokio::time::sleep_until(deadline.into()) => {}
+                        _ = seal.notified() => {}
+                    }
+                }
+                // wait for the first transaction of the batch, for the batch
+                // to fill up, or for the batch being sent to be included
+                None => seal.notified().await,
+            }
+
             let mut state = state_copy_for_batches.lock().await;
-            let _ = state.build_batch().await.unwrap();
-            std::thread::sleep(std::time::Duration::from_secs(10));
+            state.prune_pending();
+            state.seal_due(Instant::now());
+            send_sealed_batch(&mut state, &batches);
         }
     });
 
@@ -352,20 +1402,93 @@ async fn main() {
     tracing_subscriber::fmt::init();
 
     let app = Router::new()
-        // `GET /nonce` gets user nonce (see nonce function)
+        // `GET /nonce?user=&app=` gets user nonce (see nonce function)
         .route("/nonce", get(get_nonce))
+        // `GET /balance/:user` gets user balance
+        .route("/balance/:user", get(get_balance))
         // `GET /domain` gets the domain
         .route("/domain", get(get_domain))
-        // `GET /gas` gets price of gas (see gas function)
+        // `GET /gas` gets the network fees and the price per byte (see gas function)
         .route("/gas", get(gas_price))
+        // `POST /typed-data` builds the typed data a wallet signs
+        .route("/typed-data", post(typed_data))
         // `POST /transaction` posts a transaction
         .route("/transaction", post(submit_transaction))
+        // `POST /transaction/simulate` checks a transaction without sending it
+        .route("/transaction/simulate", post(simulate_transaction))
+        // `GET /transaction/:hash` tells what became of a transaction
+        .route("/transaction/:hash", get(get_transaction))
+        // `GET /events` streams transaction and batch events (see events function)
+        .route("/events", get(get_events))
         // `GET /batch` posts a transaction
         .route("/batch", get(get_batch))
+        // `GET /state_root` gets the commitment to nonces and balances
+        .route("/state_root", get(get_state_root))
         .with_state(shared_state);
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    let listen = state_copy_for_shutdown
+        .lock()
+        .await
+        .config
+        .api_listen
+        .clone();
+    let listener = tokio::net::TcpListener::bind(&listen)
+        .await
+        .with_context(|| format!("Could not listen on {}", listen))?;
+    println!("Serving the API on {}", listen);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+
+    // post what is left in the open batch, and wait for it to settle before
+    // saving the state and leaving, but not forever: L1 may be stuck
+    let timeout = state_copy_for_shutdown
+        .lock()
+        .await
+        .config
+        .shutdown_timeout_secs;
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+    loop {
+        let mut state = state_copy_for_shutdown.lock().await;
+        state.seal_batch();
+        send_sealed_batch(&mut state, &batches_for_shutdown);
+        let settled = state.settled();
+        if settled || Instant::now() >= deadline {
+            if !settled {
+                println!(
+                    "Gave up waiting for {} batches after {}s",
+                    state.sealed.len(),
+                    timeout
+                );
+            }
+            state.rewrite_wal();
+            state.write_snapshot();
+            break;
+        }
+        drop(state);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    Ok(())
+}
+
+// hands the next queued batch to the submitter, if it can take one. this is
+// done while holding the state, so that a batch undone by the submitter is
+// never left in the channel
+fn send_sealed_batch(state: &mut Lambda, batches: &mpsc::Sender<Batch>) {
+    if let Some(batch) = state.next_batch() {
+        // there is room, as the previous batch was already taken and included
+        if let Err(e) = batches.try_send(batch) {
+            println!("Could not hand the batch to the submitter: {}", e);
+            state.batch_not_taken();
+        }
+    }
+}
+
+async fn shutdown_signal() {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Could not listen for ctrl-c");
+    println!("Shutting down...");
 }
 
 async fn get_batch(State(state): State<Arc<LambdaMutex>>) -> (StatusCode, Json<BatchBuilder>) {
@@ -375,32 +1498,28 @@ async fn get_batch(State(state): State<Arc<LambdaMutex>>) -> (StatusCode, Json<B
     )
 }
 
+// the next nonce the user can send to the app, counting the transactions
+// already accepted into a batch
 async fn get_nonce(
     State(state): State<Arc<LambdaMutex>>,
-    Json(payload): Json<NonceIdentifier>,
+    Query(query): Query<NonceIdentifier>,
 ) -> (StatusCode, Json<Nonce>) {
     println!(
         "Getting nonce from user {:?} to application {:?}",
-        payload.user, payload.application
+        query.user, query.app
     );
     let lambda = state.lock().await;
-    let nonce = lambda
-        .wallet_state
-        .app_nonces
-        .get(&payload.application)
-        .map(|app_nonces| app_nonces.get_nonce(&payload.user))
-        .unwrap_or(Some(&0))
-        .unwrap_or(&0);
+    let nonce = lambda.expected_nonce(&query.app, &query.user);
 
-    let result = Nonce { nonce: *nonce };
+    let result = Nonce { nonce };
     (StatusCode::OK, Json(result))
 }
 
-// the input to `nonce` handler
+// the query of `nonce` handler
 #[derive(Serialize, Deserialize, Debug)]
 struct NonceIdentifier {
     user: Address,
-    application: Address,
+    app: Address,
 }
 
 // the output of `nonce` handler
@@ -409,38 +1528,166 @@ struct Nonce {
     nonce: u64,
 }
 
+// the balance of the user, counting the transactions already accepted
+async fn get_balance(
+    State(state): State<Arc<LambdaMutex>>,
+    axum::extract::Path(user): axum::extract::Path<Address>,
+) -> (StatusCode, Json<Balance>) {
+    let lambda = state.lock().await;
+    let balance = lambda
+        .wallet_state
+        .balances
+        .get(&user)
+        .copied()
+        .unwrap_or_default();
+    (StatusCode::OK, Json(Balance { balance }))
+}
+
+// the output of `balance` handler
+#[derive(Serialize)]
+struct Balance {
+    balance: U256,
+}
+
 async fn gas_price(
     State(state): State<Arc<LambdaMutex>>,
-) -> Result<(StatusCode, Json<u128>), (StatusCode, String)> {
-    match get_gas_price(state).await {
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
-        Ok(gas) => Ok((StatusCode::OK, Json(gas))),
+) -> Result<(StatusCode, Json<FeeQuote>), (StatusCode, String)> {
+    match get_gas_price(&state).await {
+        Err(e) => Err((StatusCode::SERVICE_UNAVAILABLE, e.to_string())),
+        Ok(quote) => Ok((StatusCode::OK, Json(quote))),
     }
 }
 
-async fn get_gas_price(state: Arc<LambdaMutex>) -> Result<u128, Error> {
-    Ok(state.lock().await.provider.get_gas_price().await?)
+// reads the quote kept by the fee oracle, without going to the chain
+async fn get_gas_price(state: &LambdaMutex) -> Result<FeeQuote, Error> {
+    let quote = *state.lock().await.fees.borrow();
+    quote.ok_or(anyhow!("DA price not available yet"))
 }
 
-async fn get_domain(State(_state): State<Arc<LambdaMutex>>) -> (StatusCode, Json<Eip712Domain>) {
-    (StatusCode::OK, Json(DOMAIN))
+// the root of what is final on L1, which the payment app agrees with
+async fn get_state_root(State(state): State<Arc<LambdaMutex>>) -> (StatusCode, Json<StateRoot>) {
+    let state_root = state.lock().await.confirmed_state().state_root();
+    (StatusCode::OK, Json(StateRoot { state_root }))
 }
 
-async fn submit_transaction(
+// the output of `state_root` handler
+#[derive(Serialize)]
+struct StateRoot {
+    state_root: B256,
+}
+
+// pushes the events matching `filter` as server-sent events, for as long as
+// the client stays connected
+async fn get_events(
     State(state): State<Arc<LambdaMutex>>,
-    Json(payload): Json<WireTransaction>,
-) -> Result<(StatusCode, ()), (StatusCode, String)> {
-    let signed_transaction = &payload.to_signed_transaction();
-    if let Err(e) = signed_transaction.recover(&DOMAIN) {
-        return Err((StatusCode::UNAUTHORIZED, e.to_string()));
-    };
-    // TODO: add logic to calculate wei per byte, now it is wei per gas
-    // TODO: send the gas logic to the specific DA backend
-    let gas_price = match get_gas_price(state.clone()).await {
-        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
-        Ok(g) => g,
-    };
-    if payload.max_gas_price < gas_price {
+    Query(filter): Query<EventFilter>,
+) -> Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
+    let events = state.lock().await.tracker.subscribe();
+    let stream = stream::unfold(events, move |mut events| async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                // the client missed some events, tell it so it can catch up
+                Err(RecvError::Lagged(missed)) => {
+                    let event = sse::Event::default()
+                        .event("lagged")
+                        .data(missed.to_string());
+                    return Some((Ok(event), events));
+                }
+                Err(RecvError::Closed) => return None,
+            };
+            let Some(event) = filter.apply(event) else {
+                continue;
+            };
+            let data = serde_json::to_string(&event).unwrap();
+            let event = sse::Event::default().event(event.kind()).data(data);
+            return Some((Ok(event), events));
+        }
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+async fn get_domain(State(state): State<Arc<LambdaMutex>>) -> (StatusCode, Json<Eip712Domain>) {
+    (StatusCode::OK, Json(state.lock().await.domain.clone()))
+}
+
+// fills in a `SigningMessage` for the user to sign with `eth_signTypedData_v4`
+async fn typed_data(
+    State(state): State<Arc<LambdaMutex>>,
+    Json(payload): Json<TypedDataRequest>,
+) -> Result<(StatusCode, Json<TypedData<SigningMessage>>), (StatusCode, String)> {
+    let max_gas_price = match payload.max_gas_price {
+        Some(max_gas_price) => max_gas_price,
+        None => match get_gas_price(&state).await {
+            Err(e) => return Err((StatusCode::SERVICE_UNAVAILABLE, e.to_string())),
+            Ok(quote) => quote.price_per_byte,
+        },
+    };
+    let nonce = match (payload.nonce, payload.user) {
+        (Some(nonce), _) => nonce,
+        (None, Some(user)) => state.lock().await.expected_nonce(&payload.app, &user),
+        (None, None) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Either the nonce or the user is needed".to_string(),
+            ))
+        }
+    };
+    let message = SigningMessage {
+        app: payload.app,
+        nonce,
+        max_gas_price,
+        data: payload.data,
+    };
+    let domain = state.lock().await.domain.clone();
+    Ok((StatusCode::OK, Json(TypedData::new(&domain, message))))
+}
+
+// the input of `typed_data` handler; the nonce is the next one of `user`
+// and the price the current one, unless they are given
+#[derive(Serialize, Deserialize, Debug)]
+struct TypedDataRequest {
+    app: Address,
+    data: Bytes,
+    user: Option<Address>,
+    nonce: Option<u64>,
+    max_gas_price: Option<u128>,
+}
+
+async fn submit_transaction(
+    State(state): State<Arc<LambdaMutex>>,
+    Json(payload): Json<WireTransaction>,
+) -> Result<(StatusCode, Json<AcceptedTransaction>), (StatusCode, String)> {
+    let signed_transaction = &payload.to_signed_transaction();
+    let mut accepted = AcceptedTransaction {
+        hash: payload.hash(),
+        preconfirmation: None,
+    };
+    let (domain, max_bytes) = {
+        let state_lock = state.lock().await;
+        (state_lock.domain.clone(), state_lock.config.max_batch_bytes)
+    };
+    let sender = match signed_transaction.recover(&domain) {
+        Err(e) => return Err((StatusCode::UNAUTHORIZED, e.to_string())),
+        Ok(sender) => sender,
+    };
+    // no batch could ever hold it
+    if payload.encoded_len() > max_bytes {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "Transaction takes {} bytes, batches hold at most {}",
+                payload.encoded_len(),
+                max_bytes
+            ),
+        ));
+    }
+    // transactions pay `max_gas_price` per byte of data, which must cover the DA price
+    let gas_price = match get_gas_price(&state).await {
+        Err(e) => return Err((StatusCode::SERVICE_UNAVAILABLE, e.to_string())),
+        Ok(quote) => quote.price_per_byte,
+    };
+    if payload.max_gas_price < gas_price {
         return Err((
             StatusCode::PAYMENT_REQUIRED,
             format!(
@@ -451,23 +1698,85 @@ async fn submit_transaction(
         ));
     }
     let mut state_lock = state.lock().await;
-    let sequencer_address = state_lock.config.sequencer_address.clone();
-    let transaction_opt = state_lock
-        .wallet_state
-        .verify_single(sequencer_address, &payload);
-    state_lock.batch_builder.add(signed_transaction.clone());
-    if let None = transaction_opt {
+    state_lock.prune_pending();
+    // transactions slightly ahead of the expected nonce wait for the gap to be filled
+    let expected_nonce = state_lock.expected_nonce(&payload.app, &sender);
+    if state_lock.pending.accepts(expected_nonce, payload.nonce) {
+        state_lock.hold(sender, signed_transaction.clone());
+        return Ok((StatusCode::ACCEPTED, Json(accepted)));
+    }
+    if !state_lock.has_room(payload.encoded_len()) {
         return Err((
-            StatusCode::NOT_ACCEPTABLE,
-            "Transaction not valid".to_string(),
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Too many batches are waiting to be posted".to_string(),
         ));
+    }
+    // only accepted transactions enter the batch; rejections leave the state untouched
+    match state_lock.admit(signed_transaction.clone()) {
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        Ok(None) => {
+            return Err((
+                StatusCode::NOT_ACCEPTABLE,
+                "Transaction not valid".to_string(),
+            ))
+        }
+        Ok(Some(_)) => {}
     };
-    Ok((StatusCode::CREATED, ()))
+    let preconfirmation = state_lock.preconfirmation(accepted.hash);
+    state_lock.promote_pending(payload.app, sender);
+    // the key may be remote, so the promise is signed without holding the state
+    let (signer, domain) = (state_lock.signer.clone(), state_lock.domain.clone());
+    drop(state_lock);
+    let signed = match preconfirmation {
+        Ok(preconfirmation) => signer.sign_preconfirmation(preconfirmation, &domain).await,
+        Err(e) => Err(e),
+    };
+    // the transaction is in anyway, the promise is only a bonus
+    match signed {
+        Ok(signed) => accepted.preconfirmation = Some(signed),
+        Err(e) => println!("Could not sign preconfirmation: {}", e),
+    }
+    Ok((StatusCode::CREATED, Json(accepted)))
+}
+
+// the output of `submit_transaction` handler; held transactions get no
+// preconfirmation, as their place in the batch is not known yet
+#[derive(Serialize, Deserialize)]
+struct AcceptedTransaction {
+    hash: B256,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    preconfirmation: Option<SignedPreconfirmation>,
+}
+
+// tells whether `POST /transaction` would accept the transaction, and at
+// what cost; the answer is only an error if the DA price is not known yet
+async fn simulate_transaction(
+    State(state): State<Arc<LambdaMutex>>,
+    Json(payload): Json<WireTransaction>,
+) -> Result<(StatusCode, Json<Simulation>), (StatusCode, String)> {
+    let price_per_byte = match get_gas_price(&state).await {
+        Err(e) => return Err((StatusCode::SERVICE_UNAVAILABLE, e.to_string())),
+        Ok(quote) => quote.price_per_byte,
+    };
+    let simulation = state.lock().await.simulate(&payload, price_per_byte);
+    Ok((StatusCode::OK, Json(simulation)))
+}
+
+async fn get_transaction(
+    State(state): State<Arc<LambdaMutex>>,
+    axum::extract::Path(hash): axum::extract::Path<B256>,
+) -> Result<(StatusCode, Json<TxRecord>), (StatusCode, String)> {
+    match state.lock().await.tracker.get(&hash) {
+        Some(record) => Ok((StatusCode::OK, Json(record.clone()))),
+        None => Err((StatusCode::NOT_FOUND, "Transaction not found".to_string())),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use alloy_core::primitives::{address, U256};
+    use alloy_core::sol_types::SolCall;
     use alloy_signer::SignerSync;
     use axum::{
         body::{Body, Bytes},
@@ -475,77 +1784,117 @@ mod tests {
         response::Response,
         routing::RouterIntoService,
     };
+    use da::Inclusion;
     use http_body_util::BodyExt; // for `collect`
-    use message::{SignedTransaction, SigningMessage};
+    use message::{AppNonces, SignedTransaction, SigningMessage};
     use mime;
+    use oracle::NetworkFees;
     use serde_json::json;
+    use status::TxStatus;
+    use submitter::post_batch;
     use tower::Service;
     use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
 
+    // per byte, above what anvil charges for calldata
+    const MAX_GAS_PRICE: u128 = 100_000_000_000;
+
+    pub fn mock_state() -> WalletState {
+        let john_address = address!("0000000000000000000000000000000000000099");
+        let joe_address = address!("0000000000000000000000000000000000000045");
+        let app1_address = address!("0000000000000000000000000000000000000003");
+        let app2_address = address!("0000000000000000000000000000000000000023");
+        let signer_address = address!("7306897365c277A6951FDA9519fD0CCc16341E4A");
+        let mut app1_nonces: AppNonces = AppNonces::default();
+        app1_nonces.set_nonce(john_address, 3);
+        app1_nonces.set_nonce(joe_address, 15);
+        let mut app2_nonces: AppNonces = AppNonces::default();
+        app2_nonces.set_nonce(john_address, 22);
+        let mut wallet_state: WalletState = WalletState::new(DOMAIN);
+        wallet_state.add_app_nonce(app1_address, app1_nonces);
+        wallet_state.add_app_nonce(app2_address, app2_nonces);
+        wallet_state.deposit(john_address, U256::from(2000000000));
+        wallet_state.deposit(joe_address, U256::from(321));
+        wallet_state.deposit(signer_address, U256::from(2000000000));
+        wallet_state
+    }
+
     async fn mock_lambda() -> Lambda {
         let config_string = fs::read_to_string("config.toml").unwrap();
         let mut config: Config = toml::from_str(&config_string).unwrap();
 
-        let wallet_state = mock_state();
+        let mut wallet_state = mock_state();
+        let open = wallet_state.checkpoint();
 
         let anvil = Anvil::new().try_spawn().expect("Anvil not working");
-        if USE_LOCAL_ANVIL {
-            let rpc_url: String = anvil.endpoint().parse().expect("Could not get Anvil's url");
-            config.base_url = rpc_url.clone();
-        }
 
         let signer: LocalWallet = anvil.keys()[0].clone().into();
 
-        let sequencer_address = config
-            .sequencer_signer_string
-            .parse::<alloy_signer_wallet::LocalWallet>()
-            .expect("Could not parse sequencer signature");
+        let sequencer = config.sequencer_signer().await.unwrap();
 
         let provider = ProviderBuilder::new()
             .with_recommended_fillers()
             .signer(EthereumSigner::from(signer.clone()))
             .on_http(config.base_url.clone().parse().unwrap());
 
-        if DEPLOY_INPUT_BOX {
-            let nonce = provider
-                .get_transaction_count(signer.address())
-                .await
-                .unwrap();
-            config.input_box_address = InputBox::deploy_builder(provider.clone())
-                .nonce(nonce)
-                .from(signer.address())
-                .deploy()
-                .await
-                .unwrap()
-        }
+        config.input_box_address = deploy_input_box(provider.clone(), signer.address())
+            .await
+            .unwrap();
 
         fund_sequencer(
             signer.address(),
-            sequencer_address.address(),
+            sequencer.address(),
+            DEV_FUNDS,
             Box::new(provider.clone()),
         )
-        .await;
+        .await
+        .unwrap();
 
-        let balance = provider
-            .get_balance(sequencer_address.address())
-            .await
-            .unwrap();
+        let balance = provider.get_balance(sequencer.address()).await.unwrap();
         println!(
             "mock_lambda: balance = {:?}, address = {:?}",
             balance,
-            sequencer_address.address()
+            sequencer.address()
         );
 
+        let da = config.da_backend(&sequencer, Box::new(provider)).unwrap();
+        let fees = config.fee_oracle(da.clone()).await;
         Lambda {
             wallet_state,
+            domain: config.domain(),
+            final_batches: 0,
+            signer: sequencer,
+            sealed: VecDeque::new(),
+            queued: 0,
+            sending: false,
+            open,
             batch_builder: BatchBuilder::new(config.sequencer_address),
+            scheduler: config.batch_scheduler(),
+            seal: Arc::new(Notify::new()),
+            pending: config.pending_pool(),
+            tracker: TxTracker::default(),
+            wal: None,
+            da,
+            fees,
             config,
-            provider: Box::new(provider),
             _anvil_instance: Some(anvil),
         }
     }
 
     fn produce_tx(nonce: u64, gas: u128) -> WireTransaction {
+        produce_tx_with_signer(&LocalWallet::random(), nonce, gas)
+    }
+
+    // for the modules that keep transactions as they were signed
+    pub fn produce_signed_tx(signer: &LocalWallet, nonce: u64) -> SignedTransaction {
+        produce_tx_with_signer(signer, nonce, 1).to_signed_transaction()
+    }
+
+    // a path in the temp dir, unique to the test run
+    pub fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("tripa-{}-{}", name, std::process::id()))
+    }
+
+    pub fn produce_tx_with_signer(signer: &LocalWallet, nonce: u64, gas: u128) -> WireTransaction {
         let json = format!(
             r#"
         {{
@@ -557,7 +1906,6 @@ mod tests {
         "#
         );
         let v: SigningMessage = serde_json::from_str(&json).unwrap();
-        let signer = LocalWallet::random();
         let signature = signer.sign_typed_data_sync(&v, &DOMAIN).unwrap();
         let signed_transaction = SignedTransaction {
             message: v,
@@ -566,6 +1914,49 @@ mod tests {
         WireTransaction::from_signed_transaction(&signed_transaction)
     }
 
+    // a DA layer that turns every batch down
+    struct FailingDa;
+
+    #[async_trait::async_trait]
+    impl DaBackend for FailingDa {
+        async fn submit(&self, _batch: &Batch) -> Result<Submission, Error> {
+            Err(anyhow!("batch turned down"))
+        }
+
+        async fn network_fees(&self) -> Result<NetworkFees, Error> {
+            Err(anyhow!("no fees"))
+        }
+
+        fn price_per_byte(&self, _fees: &NetworkFees) -> u128 {
+            0
+        }
+
+        async fn inclusion(&self, _submission: &Submission) -> Result<Inclusion, Error> {
+            Ok(Inclusion::Missing)
+        }
+    }
+
+    // seals the open batch and hands it to a submitter, like the batch task
+    // does, returning once the submitter is done with it
+    async fn post_open_batch(state: &Arc<LambdaMutex>) {
+        let (batches, sealed_batches) = mpsc::channel::<Batch>(1);
+        let submitter = {
+            let mut state_lock = state.lock().await;
+            state_lock.seal_batch();
+            send_sealed_batch(&mut state_lock, &batches);
+            Submitter::new(
+                state_lock.da.clone(),
+                state_lock.config.retry_policy(),
+                state_lock.config.confirmation_depth,
+                state.clone(),
+                sealed_batches,
+            )
+        };
+        // the submitter stops once the channel is empty and closed
+        drop(batches);
+        submitter.run().await;
+    }
+
     /// Having a function that produces our app makes it easy to call it from tests
     /// without having to create an HTTP server.
     async fn app() -> (Router, Arc<Mutex<Lambda>>) {
@@ -575,10 +1966,16 @@ mod tests {
         (
             Router::new()
                 .route("/nonce", get(get_nonce))
+                .route("/balance/:user", get(get_balance))
                 .route("/gas", get(gas_price))
                 .route("/domain", get(get_domain))
+                .route("/typed-data", post(typed_data))
                 .route("/transaction", post(submit_transaction))
+                .route("/transaction/simulate", post(simulate_transaction))
+                .route("/transaction/:hash", get(get_transaction))
+                .route("/events", get(get_events))
                 .route("/batch", get(get_batch))
+                .route("/state_root", get(get_state_root))
                 .with_state(shared_state),
             returned_state,
         )
@@ -616,8 +2013,14 @@ mod tests {
             .call(make_request(false, "/gas", Body::empty()))
             .await
             .unwrap();
-        let (status, _body) = extract_parts(response).await;
+        let (status, body) = extract_parts(response).await;
         assert_eq!(status, StatusCode::OK);
+        let quote: FeeQuote = serde_json::from_slice(&body).unwrap();
+        // calldata is priced from the EIP-1559 fees, with the markup on top
+        let fees = quote.fees;
+        assert!(fees.base_fee > 0);
+        assert!(quote.price_per_byte >= 16 * (fees.base_fee + fees.priority_fee));
+        assert!(quote.price_per_byte < MAX_GAS_PRICE);
     }
 
     #[tokio::test]
@@ -652,7 +2055,7 @@ mod tests {
     #[tokio::test]
     async fn transaction_low_balance() {
         let (app, _) = app().await;
-        let transaction = produce_tx(21, 2000000000);
+        let transaction = produce_tx(21, MAX_GAS_PRICE);
         let response = app
             .oneshot(make_request(
                 true,
@@ -666,10 +2069,33 @@ mod tests {
         assert_eq!(&body[..], b"Transaction not valid");
     }
 
+    #[tokio::test]
+    async fn transaction_rejected_not_batched() {
+        let (app, state) = app().await;
+        let wallet_state_before = state.lock().await.wallet_state.clone();
+        // too far ahead of the expected nonce to be held
+        let transaction = produce_tx(100, MAX_GAS_PRICE);
+        let response = app
+            .oneshot(make_request(
+                true,
+                "/transaction",
+                Body::from(serde_json::to_vec(&json!(transaction)).unwrap()),
+            ))
+            .await
+            .unwrap();
+        let (status, _body) = extract_parts(response).await;
+        assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+
+        let state_lock = state.lock().await;
+        assert!(state_lock.batch_builder.txs.is_empty());
+        assert!(state_lock.pending.is_empty());
+        assert_eq!(state_lock.wallet_state, wallet_state_before);
+    }
+
     #[tokio::test]
     async fn transaction_success() {
         let (app, _) = app().await;
-        let transaction = produce_tx(0, 2000000000);
+        let transaction = produce_tx(0, MAX_GAS_PRICE);
         let response = app
             .oneshot(make_request(
                 true,
@@ -680,7 +2106,80 @@ mod tests {
             .unwrap();
         let (status, body) = extract_parts(response).await;
         assert_eq!(status, StatusCode::CREATED);
-        assert_eq!(&body[..], b"");
+        let accepted: AcceptedTransaction = serde_json::from_slice(&body).unwrap();
+        assert_eq!(accepted.hash, transaction.hash());
+        // the first transaction of the first batch
+        let preconfirmation = accepted.preconfirmation.unwrap().preconfirmation;
+        assert_eq!(preconfirmation.tx_hash, transaction.hash());
+        assert_eq!(preconfirmation.batch, 0);
+        assert_eq!(preconfirmation.position, 0);
+    }
+
+    #[tokio::test]
+    async fn transaction_out_of_order() {
+        let (app, state) = app().await;
+        let mut service: RouterIntoService<Body> = app.into_service();
+        let signer = LocalWallet::random();
+        for (nonce, expected_status) in [
+            (2, StatusCode::ACCEPTED),
+            (1, StatusCode::ACCEPTED),
+            (0, StatusCode::CREATED),
+        ] {
+            let transaction = produce_tx_with_signer(&signer, nonce, MAX_GAS_PRICE);
+            let response = ServiceExt::<Request<Body>>::ready(&mut service)
+                .await
+                .unwrap()
+                .call(make_request(
+                    true,
+                    "/transaction",
+                    Body::from(serde_json::to_vec(&json!(transaction)).unwrap()),
+                ))
+                .await
+                .unwrap();
+            let (status, _body) = extract_parts(response).await;
+            assert_eq!(status, expected_status);
+            let (_, record) = get_status(&mut service, transaction.hash()).await;
+            let expected = match nonce {
+                0 => "pending",
+                _ => "held",
+            };
+            assert_eq!(record.unwrap()["status"], expected);
+        }
+        let first = produce_tx_with_signer(&signer, 1, MAX_GAS_PRICE);
+        let (_, record) = get_status(&mut service, first.hash()).await;
+        assert_eq!(record.unwrap()["status"], "pending");
+
+        // once the gap is filled, held transactions are moved into the batch in order
+        let state_lock = state.lock().await;
+        let nonces: Vec<u64> = state_lock
+            .batch_builder
+            .txs
+            .iter()
+            .map(|tx| tx.message.nonce)
+            .collect();
+        assert_eq!(nonces, vec![0, 1, 2]);
+        assert!(state_lock.pending.is_empty());
+        assert_eq!(
+            state_lock.expected_nonce(&Address::ZERO, &signer.address()),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_transaction_is_dropped() {
+        let (app, state) = app().await;
+        let mut service: RouterIntoService<Body> = app.into_service();
+        state.lock().await.pending = PendingPool::new(4, Duration::ZERO);
+        let transaction = produce_tx(1, MAX_GAS_PRICE);
+        assert_eq!(
+            post_transaction(&mut service, &transaction).await,
+            StatusCode::ACCEPTED
+        );
+        state.lock().await.prune_pending();
+        let (_, record) = get_status(&mut service, transaction.hash()).await;
+        let record = record.unwrap();
+        assert_eq!(record["status"], "dropped");
+        assert_eq!(record["reason"], "expired");
     }
 
     #[tokio::test]
@@ -696,7 +2195,7 @@ mod tests {
         let (status, body) = extract_parts(response).await;
         assert_eq!(status, StatusCode::OK);
         assert_eq!(&body[..], b"{\"sequencer_payment_address\":\"0x63f9725f107358c9115bc9d86c72dd5823e9b1e6\",\"txs\":[]}");
-        let transaction = produce_tx(0, 2000000000);
+        let transaction = produce_tx(0, MAX_GAS_PRICE);
         let response = ServiceExt::<Request<Body>>::ready(&mut service)
             .await
             .unwrap()
@@ -709,7 +2208,11 @@ mod tests {
             .unwrap();
         let (status, body) = extract_parts(response).await;
         assert_eq!(status, StatusCode::CREATED);
-        assert_eq!(&body[..], b"");
+        let accepted: AcceptedTransaction = serde_json::from_slice(&body).unwrap();
+        assert_eq!(accepted.hash, transaction.hash());
+        let preconfirmation = accepted.preconfirmation.unwrap().preconfirmation;
+        assert_eq!(preconfirmation.batch, 0);
+        assert_eq!(preconfirmation.position, 0);
         let response = ServiceExt::<Request<Body>>::ready(&mut service)
             .await
             .unwrap()
@@ -721,36 +2224,655 @@ mod tests {
         // here we ommit the signature and only look at the first bytes,
         // because the signature changes every time.
         assert_eq!(&body[0..169], b"{\"sequencer_payment_address\":\"0x63f9725f107358c9115bc9d86c72dd5823e9b1e6\",\"txs\":[{\"message\":{\"app\":\"0x0000000000000000000000000000000000000000\",\"nonce\":0,\"max_gas_price\"");
-        let mut state_lock = state.lock().await;
-        let _batch = state_lock.build_batch().await.unwrap();
+        let sealed = state.lock().await.batch_builder.clone().build();
+        post_open_batch(&state).await;
+        let state_lock = state.lock().await;
 
+        // the InputBox address doubles as the app, as in `addInput`
+        let input_box_address = state_lock.config.input_box_address;
         let provider = ProviderBuilder::new().on_http(state_lock.config.base_url.parse().unwrap());
+        let logs = InputBox::new(input_box_address, provider)
+            .InputAdded_filter()
+            .topic1(input_box_address.into_word())
+            .from_block(0)
+            .query()
+            .await
+            .unwrap();
+        assert_eq!(logs.len(), 1);
+        let advance = EvmAdvanceCall::abi_decode(&logs[0].0.input, true).unwrap();
+        assert_eq!(advance.msgSender, state_lock.signer.address());
+        assert_eq!(&advance.payload[..], &sealed.to_bytes()[..]);
+    }
 
-        let input_contract = InputBox::new(state_lock.config.input_box_address, provider.clone());
+    #[tokio::test]
+    async fn full_batch_is_due() {
+        let (app, state) = app().await;
+        let seal = {
+            let mut state_lock = state.lock().await;
+            state_lock.scheduler = BatchScheduler::new(BatchPolicy {
+                max_bytes: usize::MAX,
+                max_txs: 1,
+                max_age: Duration::from_secs(3600),
+            });
+            // nothing to post yet
+            assert!(!state_lock.scheduler.is_due(Instant::now()));
+            state_lock.seal.clone()
+        };
+
+        let transaction = produce_tx(0, MAX_GAS_PRICE);
+        let response = app
+            .oneshot(make_request(
+                true,
+                "/transaction",
+                Body::from(serde_json::to_vec(&json!(transaction)).unwrap()),
+            ))
+            .await
+            .unwrap();
+        let (status, _body) = extract_parts(response).await;
+        assert_eq!(status, StatusCode::CREATED);
 
-        let hash = input_contract
-            .getInputHash(state_lock.config.input_box_address, U256::from(0))
-            .call()
+        // the batch task is woken up, long before the batch gets old
+        tokio::time::timeout(Duration::from_secs(1), seal.notified())
             .await
             .unwrap();
+        assert!(state.lock().await.scheduler.is_due(Instant::now()));
+        post_open_batch(&state).await;
+        assert!(state.lock().await.scheduler.is_empty());
+    }
 
-        println!("hash = {:}", hash._0);
+    #[tokio::test]
+    async fn batches_stay_within_limits() {
+        let (app, state) = app().await;
+        let mut service: RouterIntoService<Body> = app.into_service();
+        let signer = LocalWallet::random();
+        let tx = |nonce| produce_tx_with_signer(&signer, nonce, MAX_GAS_PRICE);
+        let len = tx(0).encoded_len();
+        state.lock().await.scheduler = BatchScheduler::new(BatchPolicy {
+            max_bytes: len * 3 / 2,
+            max_txs: 1000,
+            max_age: Duration::from_secs(3600),
+        });
+
+        assert_eq!(
+            post_transaction(&mut service, &tx(0)).await,
+            StatusCode::CREATED
+        );
+        // the second transaction does not fit, so the first one is sealed alone
+        assert_eq!(
+            post_transaction(&mut service, &tx(1)).await,
+            StatusCode::CREATED
+        );
+        {
+            let state_lock = state.lock().await;
+            assert_eq!(state_lock.sealed.len(), 1);
+            assert_eq!(state_lock.sealed[0].batch.txs.len(), 1);
+            assert_eq!(state_lock.batch_builder.txs.len(), 1);
+        }
 
-        // TODO: test if batch was submitted to inputbox
+        // a transaction that no batch could hold is turned down
+        state.lock().await.config.max_batch_bytes = len - 1;
+        assert_eq!(
+            post_transaction(&mut service, &tx(2)).await,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+
+    #[tokio::test]
+    async fn replay_input_box() {
+        let (app, state) = app().await;
+        let signer = LocalWallet::random();
+        let transaction = produce_tx_with_signer(&signer, 0, MAX_GAS_PRICE);
+        let response = app
+            .oneshot(make_request(
+                true,
+                "/transaction",
+                Body::from(serde_json::to_vec(&json!(transaction)).unwrap()),
+            ))
+            .await
+            .unwrap();
+        let (status, _body) = extract_parts(response).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        post_open_batch(&state).await;
+        let state_lock = state.lock().await;
+
+        let source = replay::ReplaySource {
+            from_block: 0,
+            // every block on its own
+            block_range: 1,
+            ..state_lock.config.replay_source()
+        };
+        let (replayed, batches) = replay::replay(
+            ProviderBuilder::new().on_http(state_lock.config.base_url.parse().unwrap()),
+            &source,
+            state_lock.domain.clone(),
+        )
+        .await
+        .unwrap();
+        // the batch numbers carry on from the replayed batches
+        assert_eq!(batches, 1);
+        assert_eq!(batches, state_lock.final_batches);
+        let nonce = replayed
+            .app_nonces
+            .get(&Address::ZERO)
+            .and_then(|app_nonces| app_nonces.get_nonce(&signer.address()));
+        assert_eq!(nonce, Some(&1));
+    }
+
+    #[tokio::test]
+    async fn wal_until_posted() {
+        let (app, state) = app().await;
+        let path = temp_path("lambda.wal");
+        let (wal, _) = Wal::open(&path).unwrap();
+        state.lock().await.wal = Some(wal);
+
+        let transaction = produce_tx(0, MAX_GAS_PRICE);
+        let response = app
+            .oneshot(make_request(
+                true,
+                "/transaction",
+                Body::from(serde_json::to_vec(&json!(transaction)).unwrap()),
+            ))
+            .await
+            .unwrap();
+        let (status, _body) = extract_parts(response).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        // a restarted sequencer would find the accepted transaction in the log
+        let (_, logged) = Wal::open(&path).unwrap();
+        assert_eq!(logged.open, vec![transaction.to_signed_transaction()]);
+
+        post_open_batch(&state).await;
+        let (_, logged) = Wal::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(logged, Logged::default());
+    }
+
+    #[tokio::test]
+    async fn wal_restores_batches() {
+        let (app, state) = app().await;
+        let mut service: RouterIntoService<Body> = app.into_service();
+        let path = temp_path("restore.wal");
+        let (wal, _) = Wal::open(&path).unwrap();
+        state.lock().await.wal = Some(wal);
+        let signer = LocalWallet::random();
+        let sequencer = state.lock().await.signer.address();
+        let transactions: Vec<WireTransaction> = (0..3)
+            .map(|nonce| produce_tx_with_signer(&signer, nonce, MAX_GAS_PRICE))
+            .collect();
+        let mut promised = Vec::new();
+        for (i, transaction) in transactions.iter().enumerate() {
+            promised.push(preconfirm(&mut service, transaction, sequencer).await);
+            // two batches, the first of them included, and an open one
+            if i < 2 {
+                let mut state_lock = state.lock().await;
+                state_lock.seal_batch();
+            }
+            if i == 0 {
+                let mut state_lock = state.lock().await;
+                state_lock.next_batch().unwrap();
+                state_lock.batch_included(&Submission {
+                    tx_hash: B256::repeat_byte(1),
+                    block_number: Some(1),
+                    input_index: Some(0),
+                    nonce: None,
+                });
+            }
+        }
+
+        // a restarted sequencer, whose state is as of the last final batch
+        let (_, logged) = Wal::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let (_, restarted) = app().await;
+        let mut restarted = restarted.lock().await;
+        restarted.restore_from_wal(logged);
+        assert_eq!(restarted.sealed.len(), 2);
+        assert!(restarted.sealed[0].submission.is_some());
+        assert!(restarted.sealed[1].submission.is_none());
+        assert_eq!(restarted.queued, 1);
+        assert_eq!(restarted.batch_builder.txs.len(), 1);
+        for signed in promised {
+            let hash = signed.preconfirmation.tx_hash;
+            assert_eq!(
+                restarted.preconfirmation(hash).unwrap(),
+                signed.preconfirmation
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn settled_once_included() {
+        let (_, state) = app().await;
+        let mut state_lock = state.lock().await;
+        let tx = produce_tx(0, MAX_GAS_PRICE).to_signed_transaction();
+        state_lock.admit(tx).unwrap();
+        state_lock.seal_batch();
+        state_lock.next_batch().unwrap();
+        assert!(!state_lock.settled());
+        state_lock.batch_included(&Submission {
+            tx_hash: B256::repeat_byte(1),
+            block_number: Some(1),
+            input_index: Some(0),
+            nonce: None,
+        });
+        // without a WAL to follow it after a restart, it has to be final
+        assert!(!state_lock.settled());
+        let path = temp_path("settled.wal");
+        let (wal, _) = Wal::open(&path).unwrap();
+        state_lock.wal = Some(wal);
+        fs::remove_file(&path).unwrap();
+        assert!(state_lock.settled());
+    }
+
+    // the status of a transaction, as JSON if it was found
+    async fn get_status(
+        service: &mut RouterIntoService<Body>,
+        hash: B256,
+    ) -> (StatusCode, Option<serde_json::Value>) {
+        let response = ServiceExt::<Request<Body>>::ready(service)
+            .await
+            .unwrap()
+            .call(make_request(
+                false,
+                &format!("/transaction/{}", hash),
+                Body::empty(),
+            ))
+            .await
+            .unwrap();
+        let (status, body) = extract_parts(response).await;
+        (status, serde_json::from_slice(&body).ok())
+    }
+
+    // posts a transaction that must be accepted, returning its checked preconfirmation
+    async fn preconfirm(
+        service: &mut RouterIntoService<Body>,
+        transaction: &WireTransaction,
+        sequencer: Address,
+    ) -> SignedPreconfirmation {
+        let response = ServiceExt::<Request<Body>>::ready(service)
+            .await
+            .unwrap()
+            .call(make_request(
+                true,
+                "/transaction",
+                Body::from(serde_json::to_vec(&json!(transaction)).unwrap()),
+            ))
+            .await
+            .unwrap();
+        let (status, body) = extract_parts(response).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let accepted: AcceptedTransaction = serde_json::from_slice(&body).unwrap();
+        let signed = accepted.preconfirmation.unwrap();
+        assert!(signed.verify(&DOMAIN, sequencer));
+        assert_eq!(signed.preconfirmation.tx_hash, transaction.hash());
+        signed
+    }
+
+    async fn post_transaction(
+        service: &mut RouterIntoService<Body>,
+        transaction: &WireTransaction,
+    ) -> StatusCode {
+        let response = ServiceExt::<Request<Body>>::ready(service)
+            .await
+            .unwrap()
+            .call(make_request(
+                true,
+                "/transaction",
+                Body::from(serde_json::to_vec(&json!(transaction)).unwrap()),
+            ))
+            .await
+            .unwrap();
+        extract_parts(response).await.0
+    }
+
+    #[tokio::test]
+    async fn admission_while_in_flight() {
+        let (app, state) = app().await;
+        let mut service: RouterIntoService<Body> = app.into_service();
+        let signer = LocalWallet::random();
+        let confirmed_nonce = |lambda: &Lambda| {
+            lambda
+                .confirmed_state()
+                .app_nonces
+                .get(&Address::ZERO)
+                .and_then(|app_nonces| app_nonces.get_nonce(&signer.address()))
+                .copied()
+        };
+
+        let transaction = produce_tx_with_signer(&signer, 0, MAX_GAS_PRICE);
+        assert_eq!(
+            post_transaction(&mut service, &transaction).await,
+            StatusCode::CREATED
+        );
+        let (batch, da, policy) = {
+            let mut state_lock = state.lock().await;
+            state_lock.seal_batch();
+            let batch = state_lock.next_batch().unwrap();
+            // a single batch is in flight at a time
+            assert!(state_lock.next_batch().is_none());
+            let policy = state_lock.config.retry_policy();
+            (batch, state_lock.da.clone(), policy)
+        };
+
+        // the state is not held while the batch is in flight
+        let transaction = produce_tx_with_signer(&signer, 1, MAX_GAS_PRICE);
+        assert_eq!(
+            post_transaction(&mut service, &transaction).await,
+            StatusCode::CREATED
+        );
+        let submission = post_batch(da.as_ref(), &policy, &batch).await.unwrap();
+
+        let mut state_lock = state.lock().await;
+        assert_eq!(confirmed_nonce(&state_lock), None);
+        state_lock.batch_included(&submission);
+        // included, but not final yet
+        assert_eq!(confirmed_nonce(&state_lock), None);
+        state_lock.batch_finalized();
+        assert_eq!(confirmed_nonce(&state_lock), Some(1));
+        assert_eq!(
+            state_lock.expected_nonce(&Address::ZERO, &signer.address()),
+            2
+        );
+        assert_eq!(state_lock.batch_builder.txs.len(), 1);
+        drop(state_lock);
+
+        post_open_batch(&state).await;
+        assert_eq!(confirmed_nonce(&*state.lock().await), Some(2));
+    }
+
+    #[tokio::test]
+    async fn reorged_batch_rolls_back_later_ones() {
+        let (app, state) = app().await;
+        let mut service: RouterIntoService<Body> = app.into_service();
+        let signer = LocalWallet::random();
+        for nonce in 0..3 {
+            let transaction = produce_tx_with_signer(&signer, nonce, MAX_GAS_PRICE);
+            assert_eq!(
+                post_transaction(&mut service, &transaction).await,
+                StatusCode::CREATED
+            );
+            // the last transaction stays in the open batch
+            if nonce < 2 {
+                let mut state_lock = state.lock().await;
+                state_lock.seal_batch();
+                state_lock.next_batch().unwrap();
+                state_lock.batch_included(&Submission {
+                    tx_hash: B256::ZERO,
+                    block_number: None,
+                    input_index: None,
+                    nonce: None,
+                });
+            }
+        }
+
+        let mut state_lock = state.lock().await;
+        state_lock.batch_finalized();
+        let second = produce_tx_with_signer(&signer, 1, MAX_GAS_PRICE).hash();
+        let promised = state_lock.preconfirmation(second).unwrap();
+        // the second batch could not be posted again after a reorg
+        state_lock.batch_failed(0, "reorged out");
+
+        // its transactions are still valid, so it is queued again as it was,
+        // followed by the open batch
+        assert_eq!(state_lock.sealed.len(), 1);
+        assert_eq!(state_lock.queued, 1);
+        assert!(!state_lock.sending);
+        assert_eq!(state_lock.batch_builder.txs.len(), 1);
+        assert_eq!(state_lock.preconfirmation(second).unwrap(), promised);
+        assert_eq!(
+            state_lock.tracker.get(&second).unwrap().status,
+            TxStatus::Sealed
+        );
+        assert_eq!(
+            state_lock.expected_nonce(&Address::ZERO, &signer.address()),
+            3
+        );
+        // until it is posted, only the first batch counts
+        let confirmed = state_lock.confirmed_state();
+        let nonce = confirmed
+            .app_nonces
+            .get(&Address::ZERO)
+            .and_then(|app_nonces| app_nonces.get_nonce(&signer.address()));
+        assert_eq!(nonce, Some(&1));
+    }
+
+    #[tokio::test]
+    async fn full_batches_are_queued() {
+        let (app, state) = app().await;
+        let mut service: RouterIntoService<Body> = app.into_service();
+        {
+            let mut state_lock = state.lock().await;
+            state_lock.config.max_queued_batches = 1;
+            state_lock.scheduler = BatchScheduler::new(BatchPolicy {
+                max_bytes: usize::MAX,
+                max_txs: 1,
+                max_age: Duration::from_secs(3600),
+            });
+        }
+        let signer = LocalWallet::random();
+        let tx = |nonce| produce_tx_with_signer(&signer, nonce, MAX_GAS_PRICE);
+
+        assert_eq!(
+            post_transaction(&mut service, &tx(0)).await,
+            StatusCode::CREATED
+        );
+        {
+            let mut state_lock = state.lock().await;
+            state_lock.seal_due(Instant::now());
+            state_lock.next_batch().unwrap();
+        }
+        // the second batch is sealed while the first one is being sent
+        assert_eq!(
+            post_transaction(&mut service, &tx(1)).await,
+            StatusCode::CREATED
+        );
+        state.lock().await.seal_due(Instant::now());
+        assert_eq!(state.lock().await.queued, 1);
+        // the open batch is full, and the queue too
+        assert_eq!(
+            post_transaction(&mut service, &tx(2)).await,
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            post_transaction(&mut service, &tx(3)).await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        // once the first batch is in, the second one is sent and the open
+        // batch takes its place in the queue
+        let mut state_lock = state.lock().await;
+        state_lock.batch_included(&Submission {
+            tx_hash: B256::ZERO,
+            block_number: None,
+            input_index: None,
+            nonce: None,
+        });
+        assert_eq!(state_lock.next_batch().unwrap().txs.len(), 1);
+        assert_eq!(state_lock.queued, 1);
+        assert!(state_lock.has_room(1));
+    }
+
+    #[tokio::test]
+    async fn transaction_status() {
+        let (app, state) = app().await;
+        let mut service: RouterIntoService<Body> = app.into_service();
+        let signer = LocalWallet::random();
+        state
+            .lock()
+            .await
+            .wallet_state
+            .deposit(signer.address(), U256::from(10).pow(U256::from(18)));
+        let transaction = produce_tx_with_signer(&signer, 0, MAX_GAS_PRICE);
+        let response = ServiceExt::<Request<Body>>::ready(&mut service)
+            .await
+            .unwrap()
+            .call(make_request(
+                true,
+                "/transaction",
+                Body::from(serde_json::to_vec(&json!(transaction)).unwrap()),
+            ))
+            .await
+            .unwrap();
+        let (status, body) = extract_parts(response).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let hash: B256 = serde_json::from_slice::<serde_json::Value>(&body).unwrap()["hash"]
+            .as_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(hash, transaction.hash());
+
+        let (status, record) = get_status(&mut service, hash).await;
+        assert_eq!(status, StatusCode::OK);
+        let record = record.unwrap();
+        assert_eq!(record["status"], "pending");
+        assert_eq!(record["position"], 0);
+        let expected_fee = U256::from(MAX_GAS_PRICE) * U256::from(transaction.data.len());
+        assert_eq!(record["fee"], json!(expected_fee));
+
+        post_open_batch(&state).await;
+        let (_, record) = get_status(&mut service, hash).await;
+        let record = record.unwrap();
+        // the default config finalizes batches at the first confirmation
+        assert_eq!(record["status"], "finalized");
+        assert!(record["input_index"].is_u64());
+
+        let (status, _) = get_status(&mut service, B256::ZERO).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn preconfirmations_are_kept() {
+        let (app, state) = app().await;
+        let mut service: RouterIntoService<Body> = app.into_service();
+        let signer = LocalWallet::random();
+        let sequencer = state.lock().await.signer.address();
+        let transactions: Vec<WireTransaction> = (0..3)
+            .map(|nonce| produce_tx_with_signer(&signer, nonce, MAX_GAS_PRICE))
+            .collect();
+        let first = preconfirm(&mut service, &transactions[0], sequencer).await;
+        let second = preconfirm(&mut service, &transactions[1], sequencer).await;
+        assert_eq!(
+            (first.preconfirmation.batch, first.preconfirmation.position),
+            (0, 0)
+        );
+        assert_eq!(
+            (
+                second.preconfirmation.batch,
+                second.preconfirmation.position
+            ),
+            (0, 1)
+        );
+        let batch = {
+            let mut state_lock = state.lock().await;
+            state_lock.seal_batch();
+            state_lock.next_batch().unwrap()
+        };
+        assert!(!first.is_broken_by(&DOMAIN, sequencer, &batch));
+        assert!(!second.is_broken_by(&DOMAIN, sequencer, &batch));
+
+        // the open batch comes after the sealed one
+        let third = preconfirm(&mut service, &transactions[2], sequencer).await;
+        assert_eq!(
+            (third.preconfirmation.batch, third.preconfirmation.position),
+            (1, 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn events_are_filtered_by_sender() {
+        let (app, _state) = app().await;
+        let signer = LocalWallet::random();
+        let response = app
+            .clone()
+            .oneshot(make_request(
+                false,
+                &format!("/events?sender={}", signer.address()),
+                Body::empty(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut events = response.into_body();
+
+        // only the second transaction is from the subscribed sender
+        let mut service: RouterIntoService<Body> = app.into_service();
+        for transaction in [
+            produce_tx(0, MAX_GAS_PRICE),
+            produce_tx_with_signer(&signer, 0, MAX_GAS_PRICE),
+        ] {
+            assert_eq!(
+                post_transaction(&mut service, &transaction).await,
+                StatusCode::CREATED
+            );
+        }
+
+        let frame = events.frame().await.unwrap().unwrap();
+        let text = String::from_utf8(frame.into_data().unwrap().to_vec()).unwrap();
+        assert!(text.starts_with("event: tx_accepted\n"));
+        // addresses may be checksummed
+        let sender = signer.address().to_string().to_lowercase();
+        assert!(text.to_lowercase().contains(&sender));
+    }
+
+    #[tokio::test]
+    async fn failed_submission_is_queued_again() {
+        let (app, state) = app().await;
+        let wallet_state_before = state.lock().await.wallet_state.clone();
+        let transaction = produce_tx(0, MAX_GAS_PRICE);
+        let response = app
+            .oneshot(make_request(
+                true,
+                "/transaction",
+                Body::from(serde_json::to_vec(&json!(transaction)).unwrap()),
+            ))
+            .await
+            .unwrap();
+        let (status, _body) = extract_parts(response).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let mut state_lock = state.lock().await;
+        assert_ne!(state_lock.wallet_state, wallet_state_before);
+        // the sender never gives up on a node that is down, so the batch is
+        // turned down by the DA layer instead
+        state_lock.da = Arc::new(FailingDa);
+        let wallet_state_after = state_lock.wallet_state.clone();
+        drop(state_lock);
+        post_open_batch(&state).await;
+        let state_lock = state.lock().await;
+        // the transaction was verified again, and waits to be posted again
+        assert_eq!(state_lock.wallet_state, wallet_state_after);
+        assert_eq!(state_lock.queued, 1);
+        assert_eq!(
+            state_lock.tracker.get(&transaction.hash()).unwrap().status,
+            TxStatus::Sealed
+        );
+    }
+
+    #[tokio::test]
+    async fn state_root() {
+        let (app, state) = app().await;
+        let expected = state.lock().await.wallet_state.state_root();
+        let response = app
+            .oneshot(make_request(false, "/state_root", Body::empty()))
+            .await
+            .unwrap();
+        let (status, body) = extract_parts(response).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            &body[..],
+            format!("{{\"state_root\":\"{}\"}}", expected).as_bytes()
+        );
     }
 
     #[tokio::test]
     async fn nonce_miss() {
         let (app, _) = app().await;
-        let nonce_id = NonceIdentifier {
-            application: address!("0000000000000000000000000000000000000010"),
-            user: address!("0000000000000000000000000000000000000020"),
-        };
         let response = app
             .oneshot(make_request(
                 false,
-                "/nonce",
-                Body::from(serde_json::to_vec(&json!(nonce_id)).unwrap()),
+                "/nonce?user=0x0000000000000000000000000000000000000020&app=0x0000000000000000000000000000000000000010",
+                Body::empty(),
             ))
             .await
             .unwrap();
@@ -763,16 +2885,11 @@ mod tests {
     #[tokio::test]
     async fn nonce() {
         let (app, _) = app().await;
-        let nonce_id = NonceIdentifier {
-            user: address!("0000000000000000000000000000000000000099"),
-            application: address!("0000000000000000000000000000000000000003"),
-        };
-        println!("{:?}", nonce_id);
         let response = app
             .oneshot(make_request(
                 false,
-                "/nonce",
-                Body::from(serde_json::to_vec(&json!(nonce_id)).unwrap()),
+                "/nonce?user=0x0000000000000000000000000000000000000099&app=0x0000000000000000000000000000000000000003",
+                Body::empty(),
             ))
             .await
             .unwrap();
@@ -780,4 +2897,220 @@ mod tests {
         assert_eq!(status, StatusCode::OK);
         assert_eq!(&body[..], b"{\"nonce\":3}");
     }
+
+    #[tokio::test]
+    async fn nonce_counts_the_open_batch() {
+        let (app, _) = app().await;
+        let mut service: RouterIntoService<Body> = app.into_service();
+        let signer = LocalWallet::random();
+        let transaction = produce_tx_with_signer(&signer, 0, MAX_GAS_PRICE);
+        assert_eq!(
+            post_transaction(&mut service, &transaction).await,
+            StatusCode::CREATED
+        );
+        let uri = format!("/nonce?user={}&app={}", signer.address(), Address::ZERO);
+        let response = ServiceExt::<Request<Body>>::ready(&mut service)
+            .await
+            .unwrap()
+            .call(make_request(false, &uri, Body::empty()))
+            .await
+            .unwrap();
+        let (status, body) = extract_parts(response).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(&body[..], b"{\"nonce\":1}");
+    }
+
+    async fn simulate(
+        service: &mut RouterIntoService<Body>,
+        transaction: &WireTransaction,
+    ) -> serde_json::Value {
+        let response = ServiceExt::<Request<Body>>::ready(service)
+            .await
+            .unwrap()
+            .call(make_request(
+                true,
+                "/transaction/simulate",
+                Body::from(serde_json::to_vec(&json!(transaction)).unwrap()),
+            ))
+            .await
+            .unwrap();
+        let (status, body) = extract_parts(response).await;
+        assert_eq!(status, StatusCode::OK);
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn simulation_changes_nothing() {
+        let (app, state) = app().await;
+        let mut service: RouterIntoService<Body> = app.into_service();
+        let signer = LocalWallet::random();
+        let transaction = produce_tx_with_signer(&signer, 0, MAX_GAS_PRICE);
+
+        let simulation = simulate(&mut service, &transaction).await;
+        assert_eq!(simulation["outcome"], "accepted");
+        assert_eq!(simulation["hash"], json!(transaction.hash()));
+        assert_eq!(simulation["position"], 0);
+        // the sender has no funds, so the sequencer gets nothing
+        assert_eq!(simulation["fee"], json!(U256::ZERO));
+        assert_eq!(simulation["underfunded"], true);
+        assert!(state.lock().await.batch_builder.txs.is_empty());
+        assert_eq!(
+            state
+                .lock()
+                .await
+                .expected_nonce(&Address::ZERO, &signer.address()),
+            0
+        );
+
+        assert_eq!(
+            post_transaction(&mut service, &transaction).await,
+            StatusCode::CREATED
+        );
+        let simulation = simulate(&mut service, &transaction).await;
+        assert_eq!(simulation["outcome"], "rejected");
+        assert_eq!(simulation["reason"], "nonce_too_low");
+        assert_eq!(simulation["expected"], 1);
+
+        let held = produce_tx_with_signer(&signer, 2, MAX_GAS_PRICE);
+        let simulation = simulate(&mut service, &held).await;
+        assert_eq!(simulation["outcome"], "held");
+        assert_eq!(simulation["expected_nonce"], 1);
+
+        let cheap = produce_tx_with_signer(&signer, 1, 1);
+        let simulation = simulate(&mut service, &cheap).await;
+        assert_eq!(simulation["reason"], "gas_price_too_low");
+        assert_eq!(simulation["offered"], 1);
+    }
+
+    async fn request_typed_data(
+        service: &mut RouterIntoService<Body>,
+        request: serde_json::Value,
+    ) -> (StatusCode, Bytes) {
+        let response = ServiceExt::<Request<Body>>::ready(service)
+            .await
+            .unwrap()
+            .call(make_request(
+                true,
+                "/typed-data",
+                Body::from(serde_json::to_vec(&request).unwrap()),
+            ))
+            .await
+            .unwrap();
+        extract_parts(response).await
+    }
+
+    #[tokio::test]
+    async fn typed_data_is_ready_to_sign() {
+        let (app, _) = app().await;
+        let mut service: RouterIntoService<Body> = app.into_service();
+        let signer = LocalWallet::random();
+        let request = json!({
+            "app": Address::ZERO,
+            "data": "0x48656c6c6f",
+            "user": signer.address(),
+            "max_gas_price": MAX_GAS_PRICE,
+        });
+        let (status, body) = request_typed_data(&mut service, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let typed_data: TypedData<SigningMessage> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(typed_data.primary_type, "SigningMessage");
+        assert_eq!(typed_data.domain, DOMAIN);
+        assert_eq!(typed_data.message.nonce, 0);
+
+        // what the wallet signs goes through as is
+        let signature = signer.sign_hash_sync(&typed_data.signing_hash()).unwrap();
+        let transaction = WireTransaction::from_signed_transaction(&SignedTransaction {
+            message: typed_data.message,
+            signature,
+        });
+        assert_eq!(
+            post_transaction(&mut service, &transaction).await,
+            StatusCode::CREATED
+        );
+
+        // the price is the current one when left out
+        let request = json!({"app": Address::ZERO, "data": "0x", "nonce": 7});
+        let (status, body) = request_typed_data(&mut service, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let typed_data: TypedData<SigningMessage> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(typed_data.message.nonce, 7);
+        assert!(typed_data.message.max_gas_price > 0);
+
+        let request = json!({"app": Address::ZERO, "data": "0x"});
+        let (status, _) = request_typed_data(&mut service, request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn balance() {
+        let (app, _) = app().await;
+        let mut service: RouterIntoService<Body> = app.into_service();
+        for (user, expected) in [
+            ("0x0000000000000000000000000000000000000099", "0x77359400"),
+            ("0x0000000000000000000000000000000000000020", "0x0"),
+        ] {
+            let response = ServiceExt::<Request<Body>>::ready(&mut service)
+                .await
+                .unwrap()
+                .call(make_request(
+                    false,
+                    &format!("/balance/{}", user),
+                    Body::empty(),
+                ))
+                .await
+                .unwrap();
+            let (status, body) = extract_parts(response).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body, format!("{{\"balance\":\"{}\"}}", expected));
+        }
+    }
+
+    #[test]
+    fn config_problems() {
+        let config_string = fs::read_to_string("config_default.toml").unwrap();
+        let mut config: Config = toml::from_str(&config_string).unwrap();
+        assert!(config.problems().is_empty());
+
+        config.base_url = "127.0.0.1:8545".to_string();
+        config.sequencer_signer_string = Some("not a key".to_string());
+        config.max_batch_txs = 0;
+        let problems = config.problems();
+        assert_eq!(problems.len(), 3);
+        assert!(problems[0].starts_with("base_url: "));
+        assert_eq!(problems[2], "max_batch_txs: must be at least 1");
+
+        // the key can only come from one place
+        let mut config: Config = toml::from_str(&config_string).unwrap();
+        config.remote_signer_url = Some("http://127.0.0.1:5006".to_string());
+        assert_eq!(
+            config.problems(),
+            vec!["sequencer_signer_string, sequencer_keystore, remote_signer_url: exactly one must be set"]
+        );
+        config.sequencer_signer_string = None;
+        assert!(config.problems().is_empty());
+    }
+
+    #[test]
+    fn integer_keys_match_the_config() {
+        let config_string = fs::read_to_string("config_default.toml").unwrap();
+        let table: toml::Table = toml::from_str(&config_string).unwrap();
+        for (key, value) in &table {
+            assert_eq!(
+                value.is_integer(),
+                Config::INTEGER_KEYS.contains(&key.as_str()),
+                "{}",
+                key
+            );
+        }
+        for key in Config::INTEGER_KEYS {
+            assert!(table.contains_key(*key), "{}", key);
+        }
+    }
+
+    #[test]
+    fn default_domain() {
+        let config_string = fs::read_to_string("config_default.toml").unwrap();
+        let config: Config = toml::from_str(&config_string).unwrap();
+        assert_eq!(config.domain(), DOMAIN);
+    }
 }
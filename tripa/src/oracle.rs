@@ -0,0 +1,155 @@
+use crate::da::DaBackend;
+use alloy_core::primitives::U128;
+use alloy_provider::Provider;
+use alloy_rpc_types::BlockNumberOrTag;
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task;
+
+/// Fees of the underlying chain, in wei.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkFees {
+    // per gas
+    pub base_fee: u128,
+    // per gas
+    pub priority_fee: u128,
+    // per blob gas, only fetched by backends that post blobs
+    pub blob_base_fee: Option<u128>,
+}
+
+/// The fees used to price transactions, as served by `GET /gas`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeQuote {
+    #[serde(flatten)]
+    pub fees: NetworkFees,
+    // what the sequencer charges, in wei per byte of transaction data;
+    // transactions must offer a `max_gas_price` of at least this much
+    pub price_per_byte: u128,
+}
+
+pub fn quote(da: &dyn DaBackend, fees: NetworkFees, markup_percent: u64) -> FeeQuote {
+    let price_per_byte = da
+        .price_per_byte(&fees)
+        .saturating_mul(100 + markup_percent as u128)
+        / 100;
+    FeeQuote {
+        fees,
+        price_per_byte,
+    }
+}
+
+pub async fn fetch_network_fees<P>(provider: &P, with_blob: bool) -> Result<NetworkFees, Error>
+where
+    P: Provider<alloy_transport_http::Http<reqwest::Client>> + ?Sized,
+{
+    let block = provider
+        .get_block_by_number(BlockNumberOrTag::Latest, false)
+        .await?
+        .ok_or(anyhow!("latest block not found"))?;
+    let base_fee = block
+        .header
+        .base_fee_per_gas
+        .ok_or(anyhow!("chain does not support EIP-1559"))?;
+    let priority_fee = provider.get_max_priority_fee_per_gas().await?;
+    let blob_base_fee = if with_blob {
+        let fee: U128 = provider.client().request("eth_blobBaseFee", ()).await?;
+        Some(fee.to())
+    } else {
+        None
+    };
+    Ok(NetworkFees {
+        base_fee,
+        priority_fee,
+        blob_base_fee,
+    })
+}
+
+/// Keeps a quote of the DA price up to date, refreshing it every `refresh`.
+/// The first quote is fetched before returning; if a refresh fails, the
+/// previous quote is kept.
+pub async fn spawn(
+    da: Arc<dyn DaBackend>,
+    refresh: Duration,
+    markup_percent: u64,
+) -> watch::Receiver<Option<FeeQuote>> {
+    let (sender, receiver) = watch::channel(None);
+    refresh_quote(da.as_ref(), &sender, markup_percent).await;
+    task::spawn(async move {
+        while !sender.is_closed() {
+            tokio::time::sleep(refresh).await;
+            refresh_quote(da.as_ref(), &sender, markup_percent).await;
+        }
+    });
+    receiver
+}
+
+async fn refresh_quote(
+    da: &dyn DaBackend,
+    sender: &watch::Sender<Option<FeeQuote>>,
+    markup_percent: u64,
+) {
+    match da.network_fees().await {
+        Ok(fees) => {
+            sender.send_replace(Some(quote(da, fees, markup_percent)));
+        }
+        Err(e) => println!("Could not refresh the DA price: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::da::{Inclusion, Submission};
+    use async_trait::async_trait;
+    use message::Batch;
+
+    const FEES: NetworkFees = NetworkFees {
+        base_fee: 10,
+        priority_fee: 5,
+        blob_base_fee: None,
+    };
+
+    // a DA layer charging the gas price for each byte
+    struct GasPerByteDa;
+
+    #[async_trait]
+    impl DaBackend for GasPerByteDa {
+        async fn submit(&self, _batch: &Batch) -> Result<Submission, Error> {
+            Err(anyhow!("not posting"))
+        }
+
+        async fn network_fees(&self) -> Result<NetworkFees, Error> {
+            Ok(FEES)
+        }
+
+        fn price_per_byte(&self, fees: &NetworkFees) -> u128 {
+            fees.base_fee + fees.priority_fee
+        }
+
+        async fn inclusion(&self, _submission: &Submission) -> Result<Inclusion, Error> {
+            Ok(Inclusion::Missing)
+        }
+    }
+
+    #[tokio::test]
+    async fn quote_with_markup() {
+        let da: Arc<dyn DaBackend> = Arc::new(GasPerByteDa);
+        assert_eq!(quote(da.as_ref(), FEES, 0).price_per_byte, 15);
+        assert_eq!(quote(da.as_ref(), FEES, 100).price_per_byte, 30);
+        // 22.5, rounded down
+        assert_eq!(quote(da.as_ref(), FEES, 50).price_per_byte, 22);
+
+        let receiver = spawn(da, Duration::from_secs(60), 10).await;
+        // 16.5, rounded down
+        assert_eq!(
+            *receiver.borrow(),
+            Some(FeeQuote {
+                fees: FEES,
+                price_per_byte: 16,
+            })
+        );
+    }
+}
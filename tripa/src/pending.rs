@@ -0,0 +1,133 @@
+use alloy_core::primitives::Address;
+use message::SignedTransaction;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+// a transaction waiting for the nonces before it to arrive
+#[derive(Clone, Debug)]
+struct HeldTransaction {
+    tx: SignedTransaction,
+    received_at: Instant,
+}
+
+/// Holds transactions whose nonce is ahead of the next expected one, keyed by
+/// (app, sender). A transaction is only held if its nonce is at most `max_gap`
+/// ahead, and it is dropped once it has been waiting for longer than `ttl`.
+#[derive(Debug)]
+pub struct PendingPool {
+    max_gap: u64,
+    ttl: Duration,
+    queues: HashMap<(Address, Address), BTreeMap<u64, HeldTransaction>>,
+}
+
+impl PendingPool {
+    pub fn new(max_gap: u64, ttl: Duration) -> Self {
+        Self {
+            max_gap,
+            ttl,
+            queues: HashMap::new(),
+        }
+    }
+
+    // tells if a transaction with `nonce` should wait for the gap to be filled
+    pub fn accepts(&self, expected_nonce: u64, nonce: u64) -> bool {
+        nonce > expected_nonce && nonce - expected_nonce <= self.max_gap
+    }
+
+    // a held transaction with the same nonce is replaced by the new one, and
+    // returned
+    pub fn hold(&mut self, sender: Address, tx: SignedTransaction) -> Option<SignedTransaction> {
+        let queue = self.queues.entry((tx.message.app, sender)).or_default();
+        let replaced = queue.insert(
+            tx.message.nonce,
+            HeldTransaction {
+                tx,
+                received_at: Instant::now(),
+            },
+        );
+        replaced.map(|held| held.tx)
+    }
+
+    // removes and returns the held transaction with exactly `nonce`, if any
+    pub fn take(&mut self, app: Address, sender: Address, nonce: u64) -> Option<SignedTransaction> {
+        let queue = self.queues.get_mut(&(app, sender))?;
+        let held = queue.remove(&nonce);
+        if queue.is_empty() {
+            self.queues.remove(&(app, sender));
+        }
+        held.map(|h| h.tx)
+    }
+
+    // drops every transaction older than the ttl, returning them
+    pub fn prune(&mut self) -> Vec<SignedTransaction> {
+        let ttl = self.ttl;
+        let mut dropped = Vec::new();
+        self.queues.retain(|_, queue| {
+            let (expired, kept) = std::mem::take(queue)
+                .into_iter()
+                .partition(|(_, held)| held.received_at.elapsed() >= ttl);
+            *queue = kept;
+            dropped.extend(expired.into_values().map(|held| held.tx));
+            !queue.is_empty()
+        });
+        dropped
+    }
+
+    pub fn len(&self) -> usize {
+        self.queues.values().map(BTreeMap::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::produce_signed_tx as produce_tx;
+    use alloy_signer_wallet::LocalWallet;
+
+    #[test]
+    fn gap_bounds() {
+        let pool = PendingPool::new(2, Duration::from_secs(60));
+        assert!(!pool.accepts(5, 4));
+        assert!(!pool.accepts(5, 5));
+        assert!(pool.accepts(5, 6));
+        assert!(pool.accepts(5, 7));
+        assert!(!pool.accepts(5, 8));
+    }
+
+    #[test]
+    fn hold_and_take() {
+        let signer = LocalWallet::random();
+        let mut pool = PendingPool::new(4, Duration::from_secs(60));
+        assert!(pool
+            .hold(signer.address(), produce_tx(&signer, 2))
+            .is_none());
+        pool.hold(signer.address(), produce_tx(&signer, 1));
+        assert_eq!(pool.len(), 2);
+        // the same nonce again replaces the held transaction
+        let replaced = pool.hold(signer.address(), produce_tx(&signer, 2));
+        assert_eq!(replaced.unwrap().message.nonce, 2);
+        assert_eq!(pool.len(), 2);
+
+        assert!(pool.take(Address::ZERO, signer.address(), 0).is_none());
+        let tx = pool.take(Address::ZERO, signer.address(), 1).unwrap();
+        assert_eq!(tx.message.nonce, 1);
+        let tx = pool.take(Address::ZERO, signer.address(), 2).unwrap();
+        assert_eq!(tx.message.nonce, 2);
+        assert_eq!(pool.len(), 0);
+    }
+
+    #[test]
+    fn prune_expired() {
+        let signer = LocalWallet::random();
+        let mut pool = PendingPool::new(4, Duration::ZERO);
+        pool.hold(signer.address(), produce_tx(&signer, 1));
+        let dropped = pool.prune();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].message.nonce, 1);
+        assert_eq!(pool.len(), 0);
+    }
+}
@@ -0,0 +1,250 @@
+use crate::{EvmAdvanceCall, InputBox};
+use alloy_core::{
+    primitives::{Address, U256},
+    sol_types::{Eip712Domain, SolCall},
+};
+use alloy_provider::Provider;
+use anyhow::{anyhow, Error};
+use message::{Batch, WalletState};
+
+/// Extracts the depositor and the amount from the payload of an input sent by
+/// the ether portal, which packs the depositor (20 bytes), the amount (32
+/// bytes) and data for the app, ignored here.
+pub fn decode_deposit(payload: &[u8]) -> Option<(Address, U256)> {
+    let depositor = Address::try_from(payload.get(..20)?).ok()?;
+    let value = U256::try_from_be_slice(payload.get(20..52)?)?;
+    Some((depositor, value))
+}
+
+// whether `payload` is what the `blob` backend posts: the versioned hashes of
+// the blobs holding the batch, which start with the KZG version byte
+fn holds_blob_hashes(payload: &[u8]) -> bool {
+    !payload.is_empty() && payload.len() % 32 == 0 && payload.chunks(32).all(|hash| hash[0] == 0x01)
+}
+
+/// Where the inputs to replay are.
+#[derive(Debug, Clone)]
+pub struct ReplaySource {
+    pub input_box_address: Address,
+    pub app: Address,
+    /// Sends the batches to count.
+    pub sequencer: Address,
+    /// Sends the deposits, if any.
+    pub ether_portal_address: Option<Address>,
+    pub from_block: u64,
+    /// An input is final once it is this many blocks deep, counting its own.
+    pub confirmation_depth: u64,
+    /// How many blocks the logs are queried for at once.
+    pub block_range: u64,
+    /// Whether batches are posted as blobs, which the logs do not hold.
+    pub blobs: bool,
+}
+
+/// Rebuilds the wallet state from every final input sent to `app` through the
+/// InputBox, exactly like the payment app does: deposits from the ether portal
+/// are credited, and batches are verified under `domain`. Also returns how many
+/// of the batches were sent by `sequencer`, which numbers its next batch; the
+/// payment address of a batch is not proof of that, as anyone can set it.
+/// Fails on an input holding blob hashes, as the blobs cannot be replayed.
+pub async fn replay<P>(
+    provider: P,
+    source: &ReplaySource,
+    domain: Eip712Domain,
+) -> Result<(WalletState, u64), Error>
+where
+    P: Provider<alloy_transport_http::Http<reqwest::Client>>,
+{
+    // the batches after this block may still be reorged out, they are put
+    // back by the WAL instead
+    let head = provider.get_block_number().await?;
+    let to_block = (head + 1).saturating_sub(source.confirmation_depth.max(1));
+    let input_contract = InputBox::new(source.input_box_address, provider);
+
+    let mut wallet_state = WalletState::new(domain);
+    let mut batches = 0;
+    let mut from_block = source.from_block;
+    // nodes limit how many blocks or logs a single query covers
+    while from_block <= to_block {
+        let range_end = to_block.min(from_block + source.block_range.max(1) - 1);
+        let logs = input_contract
+            .InputAdded_filter()
+            .topic1(source.app.into_word())
+            .from_block(from_block)
+            .to_block(range_end)
+            .query()
+            .await?;
+        for (event, _log) in logs {
+            apply_input(&mut wallet_state, &mut batches, source, &event)?;
+        }
+        from_block = range_end + 1;
+    }
+    Ok((wallet_state, batches))
+}
+
+fn apply_input(
+    wallet_state: &mut WalletState,
+    batches: &mut u64,
+    source: &ReplaySource,
+    event: &InputBox::InputAdded,
+) -> Result<(), Error> {
+    // the input of an `InputAdded` event is an ABI-encoded `EvmAdvance` call,
+    // selector included, carrying the batch or the deposit as payload
+    let advance = match EvmAdvanceCall::abi_decode(&event.input, true) {
+        Ok(advance) => advance,
+        Err(e) => {
+            println!("Skipping input {}: {}", event.index, e);
+            return Ok(());
+        }
+    };
+    if Some(advance.msgSender) == source.ether_portal_address {
+        match decode_deposit(&advance.payload) {
+            Some((depositor, value)) => {
+                wallet_state.deposit(depositor, value);
+                println!(
+                    "Replayed input {}, a deposit of {} wei from {}",
+                    event.index, value, depositor
+                );
+            }
+            None => println!("Skipping input {}: not a deposit", event.index),
+        }
+        return Ok(());
+    }
+    if source.blobs && holds_blob_hashes(&advance.payload) {
+        return Err(anyhow!(
+            "Input {} holds blob hashes, and the blobs are not in the InputBox; restore the state from a snapshot instead",
+            event.index
+        ));
+    }
+    match Batch::from_bytes(&advance.payload) {
+        Ok(batch) => {
+            if advance.msgSender == source.sequencer {
+                *batches += 1;
+            }
+            let txs = wallet_state.verify_batch(batch);
+            println!(
+                "Replayed input {} with {} transactions",
+                event.index,
+                txs.len()
+            );
+        }
+        // anyone can send inputs to the app, not all of them are batches
+        Err(e) => println!("Skipping input {}: {}", event.index, e),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(msg_sender: Address, payload: Vec<u8>, index: u64) -> InputBox::InputAdded {
+        let input = EvmAdvanceCall {
+            chainId: U256::from(1337),
+            appContract: Address::ZERO,
+            msgSender: msg_sender,
+            blockNumber: U256::from(1),
+            blockTimestamp: U256::from(2),
+            prevRandao: U256::from(3),
+            index: U256::from(index),
+            payload: payload.into(),
+        }
+        .abi_encode();
+        InputBox::InputAdded {
+            appContract: Address::ZERO,
+            index: U256::from(index),
+            input: input.into(),
+        }
+    }
+
+    #[test]
+    fn apply_inputs() {
+        let sequencer = Address::repeat_byte(9);
+        let portal = Address::repeat_byte(8);
+        let source = ReplaySource {
+            input_box_address: Address::ZERO,
+            app: Address::ZERO,
+            sequencer,
+            ether_portal_address: Some(portal),
+            from_block: 0,
+            confirmation_depth: 1,
+            block_range: 1,
+            blobs: false,
+        };
+        let batch = Batch {
+            sequencer_payment_address: sequencer,
+            txs: vec![],
+        };
+        let depositor = Address::repeat_byte(7);
+        let mut deposit = depositor.to_vec();
+        deposit.extend_from_slice(&U256::from(5).to_be_bytes::<32>());
+
+        let mut wallet_state = WalletState::new(crate::DOMAIN);
+        let mut batches = 0;
+        let inputs = [
+            input(sequencer, batch.to_bytes(), 0),
+            // anyone can post a batch paying the sequencer, it is not one of its own
+            input(Address::repeat_byte(1), batch.to_bytes(), 1),
+            input(portal, deposit.clone(), 2),
+            input(portal, deposit, 3),
+            // only the portal makes deposits
+            input(Address::repeat_byte(1), vec![0; 52], 4),
+        ];
+        for event in &inputs {
+            apply_input(&mut wallet_state, &mut batches, &source, event).unwrap();
+        }
+        assert_eq!(batches, 1);
+        assert_eq!(wallet_state.balances.get(&depositor), Some(&U256::from(10)));
+
+        // without the selector it is not an `EvmAdvance` call
+        let truncated = InputBox::InputAdded {
+            appContract: Address::ZERO,
+            index: U256::from(5),
+            input: inputs[0].input[4..].to_vec().into(),
+        };
+        apply_input(&mut wallet_state, &mut batches, &source, &truncated).unwrap();
+        assert_eq!(batches, 1);
+    }
+
+    #[test]
+    fn blobs_are_not_replayed() {
+        let mut source = ReplaySource {
+            input_box_address: Address::ZERO,
+            app: Address::ZERO,
+            sequencer: Address::repeat_byte(9),
+            ether_portal_address: None,
+            from_block: 0,
+            confirmation_depth: 1,
+            block_range: 1,
+            blobs: false,
+        };
+        let mut hashes = vec![0x01; 32];
+        hashes.extend([0x01; 32]);
+        let blob_input = input(source.sequencer, hashes, 0);
+        let mut wallet_state = WalletState::new(crate::DOMAIN);
+        let mut batches = 0;
+        // posted through calldata, it is just not a batch
+        apply_input(&mut wallet_state, &mut batches, &source, &blob_input).unwrap();
+
+        source.blobs = true;
+        assert!(apply_input(&mut wallet_state, &mut batches, &source, &blob_input).is_err());
+        let batch = Batch {
+            sequencer_payment_address: source.sequencer,
+            txs: vec![],
+        };
+        let calldata_input = input(source.sequencer, batch.to_bytes(), 1);
+        apply_input(&mut wallet_state, &mut batches, &source, &calldata_input).unwrap();
+        assert_eq!(batches, 1);
+    }
+
+    #[test]
+    fn decode_ether_deposit() {
+        let depositor = Address::repeat_byte(7);
+        let value = U256::from(123456789);
+        // what the ether portal sends: depositor, amount and data for the app
+        let mut payload = depositor.to_vec();
+        payload.extend_from_slice(&value.to_be_bytes::<32>());
+        payload.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(decode_deposit(&payload), Some((depositor, value)));
+        assert_eq!(decode_deposit(&payload[..51]), None);
+    }
+}
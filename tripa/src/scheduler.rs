@@ -0,0 +1,146 @@
+use std::time::{Duration, Instant};
+
+/// Limits of the open batch. It is sealed as soon as it reaches `max_bytes`
+/// or `max_txs`, or `max_age` after its first transaction, whichever comes first.
+#[derive(Clone, Copy, Debug)]
+pub struct BatchPolicy {
+    pub max_bytes: usize,
+    pub max_txs: usize,
+    pub max_age: Duration,
+}
+
+/// Keeps track of the open batch, to tell when it should be sealed.
+#[derive(Debug)]
+pub struct BatchScheduler {
+    policy: BatchPolicy,
+    bytes: usize,
+    txs: usize,
+    // when the first transaction entered the open batch
+    opened_at: Option<Instant>,
+}
+
+impl BatchScheduler {
+    pub fn new(policy: BatchPolicy) -> Self {
+        Self {
+            policy,
+            bytes: 0,
+            txs: 0,
+            opened_at: None,
+        }
+    }
+
+    /// Whether a transaction of `len` bytes can be added to the open batch
+    /// without going over the limits; if not, the batch is sealed first.
+    pub fn fits(&self, len: usize) -> bool {
+        self.txs < self.policy.max_txs && self.bytes.saturating_add(len) <= self.policy.max_bytes
+    }
+
+    /// Whether a transaction of `len` bytes fits in a batch at all.
+    pub fn fits_empty(&self, len: usize) -> bool {
+        len <= self.policy.max_bytes
+    }
+
+    /// Accounts for a transaction of `len` bytes added to the open batch,
+    /// returning whether the batch should now be sealed.
+    pub fn add(&mut self, len: usize) -> bool {
+        self.opened_at.get_or_insert_with(Instant::now);
+        self.bytes += len;
+        self.txs += 1;
+        self.is_full()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.bytes >= self.policy.max_bytes || self.txs >= self.policy.max_txs
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.txs == 0
+    }
+
+    /// When the open batch gets too old, if it has any transaction.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.opened_at
+            .map(|opened_at| opened_at + self.policy.max_age)
+    }
+
+    // empty batches are never due, there is nothing to post
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.is_full() || self.deadline().is_some_and(|deadline| now >= deadline)
+    }
+
+    // starts a new open batch
+    pub fn reset(&mut self) {
+        self.bytes = 0;
+        self.txs = 0;
+        self.opened_at = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduler() -> BatchScheduler {
+        BatchScheduler::new(BatchPolicy {
+            max_bytes: 100,
+            max_txs: 3,
+            max_age: Duration::from_secs(10),
+        })
+    }
+
+    #[test]
+    fn empty_is_never_due() {
+        let scheduler = scheduler();
+        assert!(scheduler.is_empty());
+        assert!(scheduler.deadline().is_none());
+        assert!(!scheduler.is_due(Instant::now() + Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn sealed_on_size() {
+        let mut scheduler = scheduler();
+        assert!(!scheduler.add(60));
+        assert!(scheduler.add(40));
+        assert!(scheduler.is_due(Instant::now()));
+
+        scheduler.reset();
+        assert!(scheduler.is_empty());
+        assert!(!scheduler.is_due(Instant::now()));
+    }
+
+    #[test]
+    fn fits_before_adding() {
+        let mut scheduler = scheduler();
+        assert!(scheduler.fits(100));
+        assert!(!scheduler.fits(101));
+        assert!(!scheduler.fits_empty(101));
+        scheduler.add(60);
+        assert!(scheduler.fits(40));
+        assert!(!scheduler.fits(41));
+        // a transaction that does not fit goes to the next batch
+        assert!(scheduler.fits_empty(41));
+        scheduler.add(1);
+        scheduler.add(1);
+        assert!(!scheduler.fits(1));
+    }
+
+    #[test]
+    fn sealed_on_count() {
+        let mut scheduler = scheduler();
+        assert!(!scheduler.add(1));
+        assert!(!scheduler.add(1));
+        assert!(scheduler.add(1));
+    }
+
+    #[test]
+    fn sealed_on_age() {
+        let mut scheduler = scheduler();
+        scheduler.add(1);
+        let deadline = scheduler.deadline().unwrap();
+        // later transactions do not push the deadline back
+        scheduler.add(1);
+        assert_eq!(scheduler.deadline(), Some(deadline));
+        assert!(!scheduler.is_due(deadline - Duration::from_secs(1)));
+        assert!(scheduler.is_due(deadline));
+    }
+}
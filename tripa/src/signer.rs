@@ -0,0 +1,270 @@
+use alloy_consensus::SignableTransaction;
+use alloy_core::primitives::{Address, B256};
+use alloy_core::sol_types::{Eip712Domain, SolStruct};
+use alloy_network::TxSigner;
+use alloy_signer::{Signature, SignerSync};
+use alloy_signer_wallet::LocalWallet;
+use anyhow::{anyhow, Context, Error};
+use async_trait::async_trait;
+use axum::{extract::State, http::StatusCode, routing::get, routing::post, Json, Router};
+use message::{Preconfirmation, SignedPreconfirmation};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+// read when the keystore has no password file
+const KEYSTORE_PASSWORD_VAR: &str = "TRIPA_KEYSTORE_PASSWORD";
+
+/// The key of the sequencer, which signs the preconfirmations and the
+/// transactions posting batches.
+#[derive(Clone, Debug)]
+pub enum SequencerSigner {
+    /// Held in memory, from the config or a keystore.
+    Local(LocalWallet),
+    /// Held by a signing service, which is asked for each signature.
+    Remote(RemoteSigner),
+}
+
+impl SequencerSigner {
+    pub fn address(&self) -> Address {
+        match self {
+            SequencerSigner::Local(wallet) => wallet.address(),
+            SequencerSigner::Remote(remote) => remote.address,
+        }
+    }
+
+    pub async fn sign_hash(&self, hash: &B256) -> Result<Signature, Error> {
+        match self {
+            SequencerSigner::Local(wallet) => Ok(wallet.sign_hash_sync(hash)?),
+            SequencerSigner::Remote(remote) => remote.sign_hash(hash).await,
+        }
+    }
+
+    /// Signs `preconfirmation` with EIP-712 under `domain`.
+    pub async fn sign_preconfirmation(
+        &self,
+        preconfirmation: Preconfirmation,
+        domain: &Eip712Domain,
+    ) -> Result<SignedPreconfirmation, Error> {
+        let signature = self
+            .sign_hash(&preconfirmation.eip712_signing_hash(domain))
+            .await?;
+        Ok(SignedPreconfirmation {
+            preconfirmation,
+            signature,
+        })
+    }
+}
+
+#[async_trait]
+impl TxSigner<Signature> for SequencerSigner {
+    fn address(&self) -> Address {
+        SequencerSigner::address(self)
+    }
+
+    // the signature hash of a transaction covers all its fields, chain id
+    // included, so the remote signer only has to sign that hash
+    async fn sign_transaction(
+        &self,
+        tx: &mut dyn SignableTransaction<Signature>,
+    ) -> alloy_signer::Result<Signature> {
+        match self {
+            SequencerSigner::Local(wallet) => TxSigner::sign_transaction(wallet, tx).await,
+            SequencerSigner::Remote(remote) => remote
+                .sign_hash(&tx.signature_hash())
+                .await
+                .map_err(alloy_signer::Error::other),
+        }
+    }
+}
+
+/// Decrypts the Web3 Secret Storage file at `path`, with the password in
+/// `password_file` or else in the `TRIPA_KEYSTORE_PASSWORD` variable.
+pub fn open_keystore(path: &Path, password_file: Option<&Path>) -> Result<LocalWallet, Error> {
+    let password = match password_file {
+        Some(password_file) => fs::read_to_string(password_file)
+            .with_context(|| format!("Could not read {}", password_file.display()))?
+            // files written by editors and `echo` end with a newline
+            .trim_end_matches(['\r', '\n'])
+            .to_string(),
+        None => std::env::var(KEYSTORE_PASSWORD_VAR).with_context(|| {
+            format!(
+                "No password for the keystore, set sequencer_keystore_password_file or {}",
+                KEYSTORE_PASSWORD_VAR
+            )
+        })?,
+    };
+    LocalWallet::decrypt_keystore(path, password)
+        .with_context(|| format!("Could not decrypt the keystore {}", path.display()))
+}
+
+/// Whether `open_keystore` would find a password without a password file.
+pub fn has_keystore_password_var() -> bool {
+    std::env::var_os(KEYSTORE_PASSWORD_VAR).is_some()
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignerAddress {
+    address: Address,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignHashRequest {
+    hash: B256,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignHashResponse {
+    signature: Signature,
+}
+
+/// A signing service reached over HTTP, which keeps the key out of tripa.
+/// It tells its address on `GET /address`, as `{"address"}`, and signs
+/// `{"hash"}` posted to `/sign` with `{"signature"}`.
+#[derive(Clone, Debug)]
+pub struct RemoteSigner {
+    url: reqwest::Url,
+    address: Address,
+    client: reqwest::Client,
+}
+
+impl RemoteSigner {
+    /// Asks the service at `url` for its address.
+    pub async fn connect(url: &str) -> Result<Self, Error> {
+        let url: reqwest::Url = url
+            .parse()
+            .with_context(|| format!("Invalid remote signer URL {}", url))?;
+        let client = reqwest::Client::new();
+        let SignerAddress { address } = client
+            .get(url.join("address")?)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(Self {
+            url,
+            address,
+            client,
+        })
+    }
+
+    pub async fn sign_hash(&self, hash: &B256) -> Result<Signature, Error> {
+        let SignHashResponse { signature } = self
+            .client
+            .post(self.url.join("sign")?)
+            .json(&SignHashRequest { hash: *hash })
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        // a signature by another key would only be found out on chain
+        let signer = signature.recover_address_from_prehash(hash)?;
+        if signer != self.address {
+            return Err(anyhow!(
+                "The remote signer signed with {} instead of {}",
+                signer,
+                self.address
+            ));
+        }
+        Ok(signature)
+    }
+}
+
+/// A signing service for `wallet`, to stand in for a real one in development.
+pub fn stand_in(wallet: LocalWallet) -> Router {
+    Router::new()
+        .route("/address", get(get_address))
+        .route("/sign", post(sign))
+        .with_state(wallet)
+}
+
+async fn get_address(State(wallet): State<LocalWallet>) -> Json<SignerAddress> {
+    Json(SignerAddress {
+        address: wallet.address(),
+    })
+}
+
+async fn sign(
+    State(wallet): State<LocalWallet>,
+    Json(request): Json<SignHashRequest>,
+) -> Result<Json<SignHashResponse>, (StatusCode, String)> {
+    match wallet.sign_hash_sync(&request.hash) {
+        Ok(signature) => Ok(Json(SignHashResponse { signature })),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::temp_path;
+    use crate::DOMAIN;
+
+    // the key of `mock_state`, encrypted with the password "tripa" and light
+    // scrypt parameters, so that the test is fast
+    const KEYSTORE: &str = r#"{
+        "crypto": {
+            "cipher": "aes-128-ctr",
+            "cipherparams": {"iv": "83dbcc02d8ccb40e466191a123791e0e"},
+            "ciphertext": "6c40e2042d82f2908b3ba0a8d821b3a6b51285759068839a8e900d56aafe4076",
+            "kdf": "scrypt",
+            "kdfparams": {
+                "dklen": 32,
+                "n": 1024,
+                "p": 1,
+                "r": 8,
+                "salt": "ab0c7876052600dd703518d6fc3fe8984592145b591fc8fb5c6d43190334ba19"
+            },
+            "mac": "3171b57e4023d2be866de4be25e23f34d3865685d173b9293c967a596e5d9649"
+        },
+        "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+        "version": 3
+    }"#;
+
+    #[test]
+    fn keystore_is_decrypted() {
+        let (path, password_file) = (temp_path("keystore.json"), temp_path("password"));
+        fs::write(&path, KEYSTORE).unwrap();
+        fs::write(&password_file, "tripa\n").unwrap();
+        let wallet = open_keystore(&path, Some(&password_file));
+        fs::write(&password_file, "wrong").unwrap();
+        let wrong = open_keystore(&path, Some(&password_file));
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&password_file).unwrap();
+
+        assert_eq!(
+            wallet.unwrap().address(),
+            "0x7306897365c277A6951FDA9519fD0CCc16341E4A"
+                .parse::<Address>()
+                .unwrap()
+        );
+        assert!(wrong.is_err());
+    }
+
+    #[tokio::test]
+    async fn remote_signer_signs_preconfirmations() {
+        let wallet = LocalWallet::random();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let service = stand_in(wallet.clone());
+        tokio::spawn(async move {
+            axum::serve(listener, service).await.unwrap();
+        });
+
+        let remote = RemoteSigner::connect(&url).await.unwrap();
+        let signer = SequencerSigner::Remote(remote);
+        assert_eq!(signer.address(), wallet.address());
+        let preconfirmation = Preconfirmation {
+            tx_hash: B256::repeat_byte(7),
+            batch: 2,
+            position: 5,
+        };
+        let signed = signer
+            .sign_preconfirmation(preconfirmation, &DOMAIN)
+            .await
+            .unwrap();
+        assert!(signed.verify(&DOMAIN, wallet.address()));
+    }
+}
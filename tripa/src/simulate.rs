@@ -0,0 +1,150 @@
+use alloy_core::primitives::{Address, B256, U256};
+use message::{Transaction, TxError, WalletState, WireTransaction};
+use serde::Serialize;
+
+/// Why `POST /transaction` would turn a transaction down.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum Rejection {
+    InvalidSignature,
+    GasPriceTooLow { offered: u128, needed: u128 },
+    NonceTooLow { expected: u64 },
+    // too far ahead to be held until the nonces before it arrive
+    NonceTooHigh { expected: u64 },
+    // bigger than a whole batch
+    TooLarge { size: usize, max: usize },
+    // the open batch is full, and so is the queue of batches to post
+    Congested,
+}
+
+impl From<TxError> for Rejection {
+    fn from(e: TxError) -> Self {
+        match e {
+            TxError::InvalidSignature => Rejection::InvalidSignature,
+            TxError::WrongNonce { expected, got } if got < expected => {
+                Rejection::NonceTooLow { expected }
+            }
+            TxError::WrongNonce { expected, .. } => Rejection::NonceTooHigh { expected },
+        }
+    }
+}
+
+/// What a transaction would get into if it were sent now.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Receipt {
+    pub hash: B256,
+    pub sender: Address,
+    pub app: Address,
+    pub nonce: u64,
+    // the number of the batch and the index in it, as a preconfirmation would promise
+    pub batch: u64,
+    pub position: usize,
+    // `max_gas_price` times the length of the data
+    pub cost: U256,
+    // paid to the sequencer, which is less than `cost` if the balance falls short
+    pub fee: U256,
+    // whether the balance falls short of `cost`
+    pub underfunded: bool,
+    // left to the sender afterwards
+    pub balance: U256,
+}
+
+/// The answer of `POST /transaction/simulate`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum Simulation {
+    /// Would enter the open batch.
+    Accepted(Receipt),
+    /// Would be held until the nonces before it arrive.
+    Held {
+        hash: B256,
+        expected_nonce: u64,
+    },
+    Rejected(Rejection),
+}
+
+/// The effects of a transaction on a scratch copy of the state.
+pub struct DryRun {
+    pub transaction: Transaction,
+    pub fee: U256,
+    /// The sender could not pay the whole cost, only what it had.
+    pub underfunded: bool,
+    pub balance: U256,
+}
+
+/// Runs `tx` as the next transaction of `wallet_state`, which is left untouched.
+pub fn dry_run(
+    wallet_state: &WalletState,
+    sequencer: Address,
+    tx: &WireTransaction,
+) -> Result<DryRun, TxError> {
+    let mut scratch = wallet_state.clone();
+    let balance = |wallet_state: &WalletState, user: Address| {
+        wallet_state
+            .balances
+            .get(&user)
+            .copied()
+            .unwrap_or_default()
+    };
+    let balance_before = balance(&scratch, sequencer);
+    let transaction = scratch.try_single(sequencer, tx)?;
+    let fee = balance(&scratch, sequencer) - balance_before;
+    Ok(DryRun {
+        fee,
+        underfunded: fee < transaction.cost().unwrap_or(U256::MAX),
+        balance: balance(&scratch, transaction.sender),
+        transaction,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::produce_tx_with_signer;
+    use crate::DOMAIN;
+    use alloy_signer_wallet::LocalWallet;
+
+    #[test]
+    fn dry_run_leaves_the_state() {
+        let sequencer = Address::repeat_byte(1);
+        let user = LocalWallet::random();
+        let mut wallet_state = WalletState::new(DOMAIN);
+        wallet_state.deposit(user.address(), U256::from(200));
+        let before = wallet_state.clone();
+
+        // 13 bytes of data at 10 wei each
+        let tx = produce_tx_with_signer(&user, 0, 10);
+        let run = dry_run(&wallet_state, sequencer, &tx).unwrap();
+        assert_eq!(run.fee, U256::from(130));
+        assert!(!run.underfunded);
+        assert_eq!(run.balance, U256::from(70));
+        assert_eq!(wallet_state, before);
+
+        let tx = produce_tx_with_signer(&user, 1, 10);
+        let rejection = dry_run(&wallet_state, sequencer, &tx)
+            .err()
+            .map(Rejection::from);
+        assert_eq!(rejection, Some(Rejection::NonceTooHigh { expected: 0 }));
+
+        let json = serde_json::to_value(Simulation::Rejected(rejection.unwrap())).unwrap();
+        assert_eq!(json["outcome"], "rejected");
+        assert_eq!(json["reason"], "nonce_too_high");
+        assert_eq!(json["expected"], 0);
+    }
+
+    #[test]
+    fn underfunded_sender_pays_what_it_has() {
+        let sequencer = Address::repeat_byte(1);
+        let user = LocalWallet::random();
+        let mut wallet_state = WalletState::new(DOMAIN);
+        wallet_state.deposit(user.address(), U256::from(100));
+
+        // costs 130, more than the deposit
+        let tx = produce_tx_with_signer(&user, 0, 10);
+        let run = dry_run(&wallet_state, sequencer, &tx).unwrap();
+        assert_eq!(run.transaction.cost(), Some(U256::from(130)));
+        assert!(run.underfunded);
+        assert_eq!(run.fee, U256::from(100));
+        assert_eq!(run.balance, U256::ZERO);
+    }
+}
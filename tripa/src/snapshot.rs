@@ -0,0 +1,104 @@
+use anyhow::{anyhow, Error};
+use message::WalletState;
+use serde::{de::IgnoredAny, Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+// bump whenever the layout of the snapshot changes
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot<S> {
+    version: u32,
+    wallet_state: S,
+    // how many batches of the sequencer the state includes
+    #[serde(default)]
+    batches: u64,
+}
+
+/// Writes the state to `path`, replacing the previous snapshot only once the
+/// new one is fully on disk.
+pub fn write(path: &Path, wallet_state: &WalletState, batches: u64) -> Result<(), Error> {
+    let snapshot = Snapshot {
+        version: SNAPSHOT_VERSION,
+        wallet_state,
+        batches,
+    };
+    let tmp_path = path.with_extension("tmp");
+    let file = fs::File::create(&tmp_path)?;
+    serde_json::to_writer(&file, &snapshot)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    sync_parent(path)
+}
+
+/// Syncs the directory holding `path`, so that a file just renamed to `path`
+/// is still there after a crash.
+pub fn sync_parent(path: &Path) -> Result<(), Error> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    fs::File::open(parent)?.sync_all()?;
+    Ok(())
+}
+
+/// Loads the snapshot at `path`, if there is one, with the number of batches
+/// it includes.
+pub fn load(path: &Path) -> Result<Option<(WalletState, u64)>, Error> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let bytes = fs::read(path)?;
+
+    // check the version before trying to parse the state
+    let header: Snapshot<IgnoredAny> = serde_json::from_slice(&bytes)?;
+    if header.version != SNAPSHOT_VERSION {
+        return Err(anyhow!(
+            "snapshot {:?} has version {}, expected {}",
+            path,
+            header.version,
+            SNAPSHOT_VERSION
+        ));
+    }
+
+    let snapshot: Snapshot<WalletState> = serde_json::from_slice(&bytes)?;
+    Ok(Some((snapshot.wallet_state, snapshot.batches)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{mock_state, temp_path};
+    use crate::DOMAIN;
+
+    #[test]
+    fn write_and_load() {
+        let path = temp_path("snapshot.json");
+        let wallet_state = mock_state();
+        write(&path, &wallet_state, 7).unwrap();
+        let (loaded, batches) = load(&path).unwrap().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(batches, 7);
+        assert_eq!(loaded, wallet_state);
+        assert_eq!(loaded.state_root(), wallet_state.state_root());
+        assert_eq!(loaded.domain, DOMAIN);
+        // a bare file name is in the working directory
+        sync_parent(Path::new("wallet_state.json")).unwrap();
+    }
+
+    #[test]
+    fn missing_snapshot() {
+        assert!(load(&temp_path("missing.json")).unwrap().is_none());
+    }
+
+    #[test]
+    fn wrong_version() {
+        let path = temp_path("version.json");
+        fs::write(&path, r#"{"version":0,"wallet_state":null}"#).unwrap();
+        let result = load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,384 @@
+use crate::da::Submission;
+use crate::events::{Event, TxSummary};
+use alloy_core::primitives::{B256, U256};
+use message::Transaction;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use tokio::sync::broadcast;
+
+// how many finalized or dropped transactions are remembered
+const MAX_FINISHED: usize = 100_000;
+// how many events a slow subscriber can fall behind before missing some
+const EVENT_BUFFER: usize = 1024;
+
+/// Where an accepted transaction stands, as served by `GET /transaction/{hash}`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TxStatus {
+    /// Waiting for the transactions with the nonces before it.
+    Held,
+    /// In the open batch.
+    Pending,
+    /// In a sealed batch that is being sent.
+    Sealed,
+    /// In a batch included on L1, which may still be reorged out.
+    Submitted {
+        l1_tx_hash: B256,
+        input_index: Option<u64>,
+    },
+    /// In a batch deep enough on L1 to be kept for good.
+    Finalized {
+        l1_tx_hash: B256,
+        input_index: Option<u64>,
+    },
+    /// Undone, it will not make it to L1.
+    Dropped { reason: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct TxRecord {
+    #[serde(flatten)]
+    pub tx: TxSummary,
+    #[serde(flatten)]
+    pub status: TxStatus,
+    // index of the transaction in its batch
+    pub position: usize,
+    // paid to the sequencer, in wei
+    pub fee: U256,
+}
+
+/// Follows the accepted transactions through the batches they are in, and
+/// publishes every step as an `Event`. The sealed batches mirror the
+/// checkpoints of `Lambda::sealed`.
+#[derive(Debug)]
+pub struct TxTracker {
+    records: HashMap<B256, TxRecord>,
+    // the transactions of the open batch, in order
+    open: Vec<B256>,
+    // the transactions of each sealed batch that is not final, oldest first
+    sealed: VecDeque<Vec<B256>>,
+    // finalized and dropped transactions, oldest first, forgotten past `MAX_FINISHED`
+    finished: VecDeque<B256>,
+    events: broadcast::Sender<Event>,
+}
+
+impl Default for TxTracker {
+    fn default() -> Self {
+        Self {
+            records: HashMap::new(),
+            open: Vec::new(),
+            sealed: VecDeque::new(),
+            finished: VecDeque::new(),
+            events: broadcast::channel(EVENT_BUFFER).0,
+        }
+    }
+}
+
+impl TxTracker {
+    pub fn get(&self, hash: &B256) -> Option<&TxRecord> {
+        self.records.get(hash)
+    }
+
+    /// Where the transaction is among the batches that are not final: the
+    /// index of its sealed batch, or the number of them if it is in the open one.
+    pub fn batch_index(&self, hash: &B256) -> Option<usize> {
+        if self.open.contains(hash) {
+            return Some(self.sealed.len());
+        }
+        self.sealed.iter().position(|batch| batch.contains(hash))
+    }
+
+    /// Receives the events published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    // a held transaction is in no batch yet, so it has no position or fee
+    pub fn held(&mut self, tx: TxSummary) {
+        let record = TxRecord {
+            tx,
+            status: TxStatus::Held,
+            position: 0,
+            fee: U256::ZERO,
+        };
+        self.records.insert(tx.hash, record);
+    }
+
+    pub fn admitted(&mut self, hash: B256, tx: &Transaction, fee: U256) {
+        let summary = TxSummary {
+            hash,
+            sender: tx.sender,
+            app: tx.app,
+        };
+        let record = TxRecord {
+            tx: summary,
+            status: TxStatus::Pending,
+            position: self.open.len(),
+            fee,
+        };
+        self.publish(Event::TxAccepted {
+            tx: summary,
+            nonce: tx.nonce,
+            position: record.position,
+        });
+        self.records.insert(hash, record);
+        self.open.push(hash);
+    }
+
+    pub fn sealed(&mut self) {
+        let batch = std::mem::take(&mut self.open);
+        self.set_status(&batch, TxStatus::Sealed);
+        self.publish(Event::BatchSealed {
+            txs: self.summaries(&batch),
+        });
+        self.sealed.push_back(batch);
+    }
+
+    // the sealed batch at `index` was included, or posted again after a reorg
+    pub fn submitted(&mut self, index: usize, submission: &Submission) {
+        let Some(batch) = self.sealed.get(index).cloned() else {
+            return;
+        };
+        let status = TxStatus::Submitted {
+            l1_tx_hash: submission.tx_hash,
+            input_index: submission.input_index,
+        };
+        self.set_status(&batch, status);
+        self.publish(Event::BatchSubmitted {
+            l1_tx_hash: submission.tx_hash,
+            input_index: submission.input_index,
+            txs: self.summaries(&batch),
+        });
+    }
+
+    pub fn finalized(&mut self) {
+        let Some(batch) = self.sealed.pop_front() else {
+            return;
+        };
+        // every transaction of the batch went out in the same L1 transaction
+        let mut finalized = None;
+        for hash in &batch {
+            if let Some(record) = self.records.get_mut(hash) {
+                if let TxStatus::Submitted {
+                    l1_tx_hash,
+                    input_index,
+                } = record.status
+                {
+                    record.status = TxStatus::Finalized {
+                        l1_tx_hash,
+                        input_index,
+                    };
+                    finalized = Some((l1_tx_hash, input_index));
+                }
+            }
+        }
+        if let Some((l1_tx_hash, input_index)) = finalized {
+            self.publish(Event::BatchFinalized {
+                l1_tx_hash,
+                input_index,
+                txs: self.summaries(&batch),
+            });
+        }
+        self.finish(batch);
+    }
+
+    // the sealed batch at `index` failed, and was undone together with every
+    // transaction accepted after it. each of them is then either admitted
+    // again, or `dropped`
+    pub fn undone(&mut self, index: usize) {
+        self.sealed.truncate(index);
+        self.open.clear();
+    }
+
+    // undone or held transactions that are no longer valid
+    pub fn dropped(&mut self, hashes: &[B256], reason: &str) {
+        let status = TxStatus::Dropped {
+            reason: reason.to_string(),
+        };
+        self.set_status(hashes, status);
+        if !hashes.is_empty() {
+            self.publish(Event::BatchDropped {
+                reason: reason.to_string(),
+                txs: self.summaries(hashes),
+            });
+        }
+        self.finish(hashes.to_vec());
+    }
+
+    fn summaries(&self, hashes: &[B256]) -> Vec<TxSummary> {
+        hashes
+            .iter()
+            .filter_map(|hash| self.records.get(hash))
+            .map(|record| record.tx)
+            .collect()
+    }
+
+    // nobody may be listening, which is fine
+    fn publish(&self, event: Event) {
+        let _ = self.events.send(event);
+    }
+
+    fn set_status(&mut self, hashes: &[B256], status: TxStatus) {
+        for hash in hashes {
+            if let Some(record) = self.records.get_mut(hash) {
+                record.status = status.clone();
+            }
+        }
+    }
+
+    fn finish(&mut self, hashes: Vec<B256>) {
+        self.finished.extend(hashes);
+        while self.finished.len() > MAX_FINISHED {
+            if let Some(hash) = self.finished.pop_front() {
+                self.records.remove(&hash);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_core::primitives::Address;
+
+    fn hash(n: u8) -> B256 {
+        B256::repeat_byte(n)
+    }
+
+    fn submission(n: u8) -> Submission {
+        Submission {
+            tx_hash: hash(100 + n),
+            block_number: Some(n as u64),
+            input_index: Some(n as u64),
+            nonce: None,
+        }
+    }
+
+    fn admit(tracker: &mut TxTracker, n: u8, fee: u64) {
+        let tx = Transaction {
+            sender: Address::repeat_byte(n),
+            app: Address::ZERO,
+            nonce: 0,
+            max_gas_price: 1,
+            data: vec![],
+        };
+        tracker.admitted(hash(n), &tx, U256::from(fee));
+    }
+
+    fn status(tracker: &TxTracker, n: u8) -> TxStatus {
+        tracker.get(&hash(n)).unwrap().status.clone()
+    }
+
+    #[test]
+    fn lifecycle() {
+        let mut tracker = TxTracker::default();
+        let mut events = tracker.subscribe();
+        admit(&mut tracker, 1, 10);
+        admit(&mut tracker, 2, 20);
+        assert_eq!(status(&tracker, 1), TxStatus::Pending);
+        assert_eq!(tracker.get(&hash(2)).unwrap().position, 1);
+        assert_eq!(tracker.get(&hash(2)).unwrap().fee, U256::from(20));
+        assert!(tracker.get(&hash(3)).is_none());
+
+        tracker.sealed();
+        admit(&mut tracker, 3, 30);
+        assert_eq!(status(&tracker, 2), TxStatus::Sealed);
+        // positions start over in the next batch
+        assert_eq!(tracker.get(&hash(3)).unwrap().position, 0);
+
+        tracker.submitted(0, &submission(0));
+        let submitted = TxStatus::Submitted {
+            l1_tx_hash: hash(100),
+            input_index: Some(0),
+        };
+        assert_eq!(status(&tracker, 1), submitted);
+        assert_eq!(status(&tracker, 3), TxStatus::Pending);
+
+        tracker.finalized();
+        assert_eq!(
+            status(&tracker, 1),
+            TxStatus::Finalized {
+                l1_tx_hash: hash(100),
+                input_index: Some(0),
+            }
+        );
+
+        let kinds: Vec<&str> = std::iter::from_fn(|| events.try_recv().ok())
+            .map(|event| event.kind())
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                "tx_accepted",
+                "tx_accepted",
+                "batch_sealed",
+                "tx_accepted",
+                "batch_submitted",
+                "batch_finalized"
+            ]
+        );
+    }
+
+    #[test]
+    fn held_until_admitted() {
+        let mut tracker = TxTracker::default();
+        let summary = |n: u8| TxSummary {
+            hash: hash(n),
+            sender: Address::repeat_byte(n),
+            app: Address::ZERO,
+        };
+        tracker.held(summary(1));
+        tracker.held(summary(2));
+        assert_eq!(status(&tracker, 1), TxStatus::Held);
+        // held transactions are in no batch
+        assert_eq!(tracker.batch_index(&hash(1)), None);
+
+        admit(&mut tracker, 1, 10);
+        assert_eq!(status(&tracker, 1), TxStatus::Pending);
+        tracker.dropped(&[hash(2)], "expired");
+        assert_eq!(
+            status(&tracker, 2),
+            TxStatus::Dropped {
+                reason: "expired".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn failure_undoes_later_batches() {
+        let mut tracker = TxTracker::default();
+        for n in 1..=3 {
+            admit(&mut tracker, n, 0);
+            if n < 3 {
+                tracker.sealed();
+                tracker.submitted(n as usize - 1, &submission(n));
+            }
+        }
+        // the first batch was reorged out and posted again
+        tracker.submitted(0, &submission(5));
+        assert_eq!(
+            status(&tracker, 1),
+            TxStatus::Submitted {
+                l1_tx_hash: hash(105),
+                input_index: Some(5),
+            }
+        );
+
+        // the second batch is admitted again, the open one is not valid anymore
+        tracker.undone(1);
+        admit(&mut tracker, 2, 0);
+        tracker.sealed();
+        tracker.dropped(&[hash(3)], "reorged out");
+        assert!(matches!(status(&tracker, 1), TxStatus::Submitted { .. }));
+        assert_eq!(status(&tracker, 2), TxStatus::Sealed);
+        assert_eq!(tracker.get(&hash(2)).unwrap().position, 0);
+        assert_eq!(tracker.batch_index(&hash(2)), Some(1));
+        assert_eq!(tracker.batch_index(&hash(3)), None);
+        assert_eq!(
+            status(&tracker, 3),
+            TxStatus::Dropped {
+                reason: "reorged out".to_string()
+            }
+        );
+    }
+}
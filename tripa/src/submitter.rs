@@ -0,0 +1,200 @@
+use crate::da::{with_backoff, DaBackend, Inclusion, RetryPolicy, Submission};
+use crate::LambdaMutex;
+use anyhow::{anyhow, Error};
+use message::Batch;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
+
+// how often the batches that are not final yet are looked at
+const CHECK_INTERVAL: Duration = Duration::from_secs(4);
+
+/// Posts a batch, returning once it has been included.
+pub async fn post_batch(
+    da: &dyn DaBackend,
+    policy: &RetryPolicy,
+    batch: &Batch,
+) -> Result<Submission, Error> {
+    let submission = da.submit(batch).await?;
+    // the batch may well be on L1 already, so only an answer from the
+    // backend can tell that it is not
+    let inclusion = with_backoff(policy, "check the batch inclusion", || {
+        da.inclusion(&submission)
+    })
+    .await;
+    if inclusion == Inclusion::Missing {
+        return Err(anyhow!(
+            "batch submitted in {} was not included",
+            submission.tx_hash
+        ));
+    }
+    Ok(submission)
+}
+
+// a batch that was included, but may still be removed by a reorg
+struct PostedBatch {
+    batch: Batch,
+    submission: Submission,
+    // when the batch was first seen missing after being included
+    missing_since: Option<Instant>,
+}
+
+/// Takes the sealed batches to the DA layer and follows them until they are
+/// `confirmation_depth` blocks deep, telling the state how each one went.
+/// Batches removed by a reorg are posted again if they do not come back on
+/// their own; if that fails, they are undone, and queued again by the state.
+pub struct Submitter {
+    da: Arc<dyn DaBackend>,
+    policy: RetryPolicy,
+    confirmation_depth: u64,
+    state: Arc<LambdaMutex>,
+    batches: mpsc::Receiver<Batch>,
+    // included batches that are not final yet, oldest first
+    unfinalized: VecDeque<PostedBatch>,
+    // how many posts failed in a row
+    failures: u32,
+}
+
+impl Submitter {
+    pub fn new(
+        da: Arc<dyn DaBackend>,
+        policy: RetryPolicy,
+        confirmation_depth: u64,
+        state: Arc<LambdaMutex>,
+        batches: mpsc::Receiver<Batch>,
+    ) -> Self {
+        Self {
+            da,
+            policy,
+            confirmation_depth,
+            state,
+            batches,
+            unfinalized: VecDeque::new(),
+            failures: 0,
+        }
+    }
+
+    /// Follows a batch that was included before a restart, until it is final.
+    pub fn follow(&mut self, batch: Batch, submission: Submission) {
+        self.unfinalized.push_back(PostedBatch {
+            batch,
+            submission,
+            missing_since: None,
+        });
+    }
+
+    pub async fn run(mut self) {
+        let mut check = tokio::time::interval(CHECK_INTERVAL);
+        check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            let batch = tokio::select! {
+                batch = self.batches.recv() => match batch {
+                    Some(batch) => Some(batch),
+                    None => return,
+                },
+                _ = check.tick(), if !self.unfinalized.is_empty() => None,
+            };
+            match batch {
+                Some(batch) => self.post(batch).await,
+                None => self.check().await,
+            }
+        }
+    }
+
+    async fn post(&mut self, batch: Batch) {
+        match post_batch(self.da.as_ref(), &self.policy, &batch).await {
+            Ok(submission) => {
+                self.failures = 0;
+                println!("Batch submitted in {} was included", submission.tx_hash);
+                self.state.lock().await.batch_included(&submission);
+                self.unfinalized.push_back(PostedBatch {
+                    batch,
+                    submission,
+                    missing_since: None,
+                });
+                self.check().await;
+            }
+            Err(e) => {
+                // the batch comes back once it is queued again, so give the
+                // DA layer some time first
+                let wait = self.policy.backoff(self.failures);
+                self.failures = self.failures.saturating_add(1);
+                println!("Could not post batch, retrying in {:?}: {}", wait, e);
+                tokio::time::sleep(wait).await;
+                // the batch comes right after the included ones
+                self.fail(self.unfinalized.len(), &e.to_string()).await;
+            }
+        }
+    }
+
+    // finalizes the batches that are deep enough, and posts again the ones
+    // that have been missing for too long
+    async fn check(&mut self) {
+        let mut index = 0;
+        // once a batch is posted again, the ones after it must follow right
+        // away, so that they stay in order
+        let mut resubmitted = false;
+        while index < self.unfinalized.len() {
+            let da = self.da.as_ref();
+            let submission = &self.unfinalized[index].submission;
+            let inclusion = with_backoff(&self.policy, "check the batch inclusion", || {
+                da.inclusion(submission)
+            })
+            .await;
+
+            let posted = &mut self.unfinalized[index];
+            match inclusion {
+                Inclusion::Included { confirmations, .. } => {
+                    posted.missing_since = None;
+                    // batches are final in order
+                    if index == 0 && confirmations >= self.confirmation_depth {
+                        let posted = self.unfinalized.pop_front().unwrap();
+                        println!("Batch submitted in {} is final", posted.submission.tx_hash);
+                        self.state.lock().await.batch_finalized();
+                        continue;
+                    }
+                }
+                Inclusion::Missing => {
+                    // a reorged transaction usually makes it back on its own
+                    let missing_since = *posted.missing_since.get_or_insert_with(Instant::now);
+                    if resubmitted || missing_since.elapsed() >= self.policy.resubmit_after {
+                        println!(
+                            "Batch submitted in {} was removed by a reorg, posting it again",
+                            posted.submission.tx_hash
+                        );
+                        match self.da.resubmit(&posted.batch, &posted.submission).await {
+                            Ok(submission) => {
+                                self.state
+                                    .lock()
+                                    .await
+                                    .batch_resubmitted(index, &submission);
+                                posted.submission = submission;
+                                posted.missing_since = None;
+                                resubmitted = true;
+                            }
+                            Err(e) => {
+                                println!("Could not post batch again: {}", e);
+                                self.fail(index, &e.to_string()).await;
+                                return;
+                            }
+                        }
+                    }
+                }
+            }
+            index += 1;
+        }
+    }
+
+    // undoes the sealed batch at `index` and the ones after it, which may
+    // depend on it; the state verifies their transactions again and queues
+    // the batches anew
+    async fn fail(&mut self, index: usize, reason: &str) {
+        self.unfinalized.truncate(index);
+        let mut state = self.state.lock().await;
+        state.batch_failed(index, reason);
+        // a batch handed over meanwhile was queued again, it must not be posted twice
+        while self.batches.try_recv().is_ok() {}
+    }
+}
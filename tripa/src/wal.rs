@@ -0,0 +1,237 @@
+use crate::da::Submission;
+use crate::snapshot::sync_parent;
+use anyhow::Error;
+use message::SignedTransaction;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum WalEntry {
+    // a transaction accepted into the open batch
+    Transaction(SignedTransaction),
+    // the transactions before this point, since the previous seal, were
+    // sealed into a batch that is not final yet
+    Sealed,
+    // the batch sealed right before was included on L1
+    Included(Submission),
+}
+
+/// A sealed batch that is not final yet, as found in the log.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LoggedBatch {
+    pub txs: Vec<SignedTransaction>,
+    /// Where the batch was included, unless it still has to be posted.
+    pub submission: Option<Submission>,
+}
+
+/// What the log holds: every accepted transaction that is not final yet.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Logged {
+    /// The sealed batches, oldest first.
+    pub batches: Vec<LoggedBatch>,
+    /// The transactions of the open batch.
+    pub open: Vec<SignedTransaction>,
+}
+
+/// Log of the accepted transactions that are not final yet, one JSON entry
+/// per line, which keeps the batches they were sealed in. Transactions and
+/// seals are appended, and synced to disk before returning; when a batch is
+/// included, final or undone, the log is rewritten as a whole.
+#[derive(Debug)]
+pub struct Wal {
+    path: PathBuf,
+    file: File,
+}
+
+impl Wal {
+    /// Opens the log at `path`, returning what it holds.
+    pub fn open(path: &Path) -> Result<(Self, Logged), Error> {
+        let mut logged = Logged::default();
+        // length of the log up to the last complete entry
+        let mut valid_len = 0;
+        if path.exists() {
+            let contents = fs::read_to_string(path)?;
+            let chunks: Vec<&str> = contents.split_inclusive('\n').collect();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let entry = match chunk.strip_suffix('\n') {
+                    Some(line) => serde_json::from_str(line).map_err(Error::from),
+                    None => Err(Error::msg("missing end of line")),
+                };
+                match entry {
+                    Ok(WalEntry::Transaction(tx)) => logged.open.push(tx),
+                    Ok(WalEntry::Sealed) => logged.batches.push(LoggedBatch {
+                        txs: std::mem::take(&mut logged.open),
+                        submission: None,
+                    }),
+                    Ok(WalEntry::Included(submission)) => {
+                        if let Some(batch) = logged.batches.last_mut() {
+                            batch.submission = Some(submission);
+                        }
+                    }
+                    // the last entry may have been cut short by a crash,
+                    // in which case it was never acknowledged
+                    Err(e) if i == chunks.len() - 1 => {
+                        println!("Dropping incomplete WAL entry: {}", e);
+                        break;
+                    }
+                    Err(e) => return Err(e),
+                }
+                valid_len += chunk.len();
+            }
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() > valid_len as u64 {
+            file.set_len(valid_len as u64)?;
+            file.sync_all()?;
+        }
+        let wal = Self {
+            path: path.to_path_buf(),
+            file,
+        };
+        Ok((wal, logged))
+    }
+
+    pub fn append_transaction(&mut self, tx: &SignedTransaction) -> Result<(), Error> {
+        self.append(&WalEntry::Transaction(tx.clone()))
+    }
+
+    pub fn seal(&mut self) -> Result<(), Error> {
+        self.append(&WalEntry::Sealed)
+    }
+
+    // replaces the log with `logged`. the new log is only swapped in once it
+    // is fully on disk
+    pub fn rewrite(&mut self, logged: &Logged) -> Result<(), Error> {
+        let mut contents = String::new();
+        let mut push = |entry: &WalEntry| -> Result<(), Error> {
+            contents += &serde_json::to_string(entry)?;
+            contents.push('\n');
+            Ok(())
+        };
+        for batch in &logged.batches {
+            for tx in &batch.txs {
+                push(&WalEntry::Transaction(tx.clone()))?;
+            }
+            push(&WalEntry::Sealed)?;
+            if let Some(submission) = &batch.submission {
+                push(&WalEntry::Included(submission.clone()))?;
+            }
+        }
+        for tx in &logged.open {
+            push(&WalEntry::Transaction(tx.clone()))?;
+        }
+
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, contents)?;
+        File::open(&tmp_path)?.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        sync_parent(&self.path)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+
+    fn append(&mut self, entry: &WalEntry) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{produce_signed_tx as produce_tx, temp_path};
+    use alloy_core::primitives::B256;
+    use alloy_signer_wallet::LocalWallet;
+
+    #[test]
+    fn reopen_keeps_batches() {
+        let path = temp_path("reopen.wal");
+        let signer = LocalWallet::random();
+        let (mut wal, logged) = Wal::open(&path).unwrap();
+        assert_eq!(logged, Logged::default());
+        wal.append_transaction(&produce_tx(&signer, 0)).unwrap();
+        wal.append_transaction(&produce_tx(&signer, 1)).unwrap();
+        wal.seal().unwrap();
+        wal.append_transaction(&produce_tx(&signer, 2)).unwrap();
+        drop(wal);
+
+        let (mut wal, logged) = Wal::open(&path).unwrap();
+        let expected = Logged {
+            batches: vec![LoggedBatch {
+                txs: vec![produce_tx(&signer, 0), produce_tx(&signer, 1)],
+                submission: None,
+            }],
+            open: vec![produce_tx(&signer, 2)],
+        };
+        // signatures of the same message by the same key are the same
+        assert_eq!(logged, expected);
+
+        wal.rewrite(&Logged::default()).unwrap();
+        drop(wal);
+        let (_wal, logged) = Wal::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(logged, Logged::default());
+    }
+
+    #[test]
+    fn rewrite_keeps_inclusions() {
+        let path = temp_path("rewrite.wal");
+        let signer = LocalWallet::random();
+        let (mut wal, _) = Wal::open(&path).unwrap();
+        let submission = Submission {
+            tx_hash: B256::repeat_byte(1),
+            block_number: Some(7),
+            input_index: Some(3),
+            nonce: None,
+        };
+        let logged = Logged {
+            batches: vec![
+                LoggedBatch {
+                    txs: vec![produce_tx(&signer, 0)],
+                    submission: Some(submission),
+                },
+                LoggedBatch {
+                    txs: vec![produce_tx(&signer, 1)],
+                    submission: None,
+                },
+            ],
+            open: vec![produce_tx(&signer, 2)],
+        };
+        wal.rewrite(&logged).unwrap();
+        // appends go to the new log
+        wal.append_transaction(&produce_tx(&signer, 3)).unwrap();
+
+        let (_, reopened) = Wal::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let mut expected = logged;
+        expected.open.push(produce_tx(&signer, 3));
+        assert_eq!(reopened, expected);
+    }
+
+    #[test]
+    fn incomplete_last_entry() {
+        let path = temp_path("incomplete.wal");
+        let signer = LocalWallet::random();
+        let (mut wal, _) = Wal::open(&path).unwrap();
+        wal.append_transaction(&produce_tx(&signer, 0)).unwrap();
+        wal.file.write_all(b"{\"transaction\":{\"mess").unwrap();
+        drop(wal);
+
+        let (mut wal, logged) = Wal::open(&path).unwrap();
+        assert_eq!(logged.open.len(), 1);
+
+        // new entries are not mixed with the dropped one
+        wal.append_transaction(&produce_tx(&signer, 1)).unwrap();
+        drop(wal);
+        let (_wal, logged) = Wal::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(logged.open.len(), 2);
+    }
+}
@@ -1,10 +1,15 @@
 echo "Getting nonce for user"
 curl -X GET \
      -i \
-     -H "Content-type: application/json" \
      -H "Accept: application/json" \
-     -d '{"user": "0x0000000000000000000000000000000000000099", "application": "0x0000000000000000000000000000000000000003"}' \
-     "0.0.0.0:3000/nonce"
+     "0.0.0.0:3000/nonce?user=0x0000000000000000000000000000000000000099&app=0x0000000000000000000000000000000000000003"
+echo -e "\n"
+
+echo "Getting balance for user"
+curl -X GET \
+     -i \
+     -H "Accept: application/json" \
+     "0.0.0.0:3000/balance/0x0000000000000000000000000000000000000099"
 echo -e "\n"
 
 echo "Getting gas price"
@@ -23,6 +28,15 @@ curl -X GET \
      "0.0.0.0:3000/domain"
 echo -e "\n"
 
+echo "Get typed data to sign"
+curl -X POST \
+     -i \
+     -H "Content-type: application/json" \
+     -H "Accept: application/json" \
+     -d '{"app": "0x0000000000000000000000000000000000000003", "data": "0x48656c6c6f", "user": "0x0000000000000000000000000000000000000099"}' \
+     "0.0.0.0:3000/typed-data"
+echo -e "\n"
+
 echo "Get batch"
 curl -X GET \
      -i \
@@ -30,3 +44,18 @@ curl -X GET \
      -H "Accept: application/json" \
      "0.0.0.0:3000/batch"
 echo -e "\n"
+
+echo "Get state root"
+curl -X GET \
+     -i \
+     -H "Content-type: application/json" \
+     -H "Accept: application/json" \
+     "0.0.0.0:3000/state_root"
+echo -e "\n"
+
+echo "Stream events for 5 seconds"
+curl -N \
+     -m 5 \
+     -H "Accept: text/event-stream" \
+     "0.0.0.0:3000/events"
+echo -e "\n"